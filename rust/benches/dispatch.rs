@@ -0,0 +1,70 @@
+//! Measures how many instructions the VM dispatches with and without the
+//! optimizer and its superinstructions.
+//!
+//! Runs each workload once with `-O0` and once with `-O1` and reports the
+//! instructions executed and the best time of each. Run with
+//! `cargo bench --bench dispatch`.
+
+use lox::Vm;
+use std::io;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 5;
+
+const WORKLOADS: [(&str, &str); 3] = [
+    (
+        "loop",
+        "var total = 0;
+         { for (var i = 0; i < 200000; i = i + 1) total = total + i % 7; }",
+    ),
+    (
+        "fib",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+         fib(20);",
+    ),
+    (
+        "locals",
+        "fun count(a, b, c) {
+           var n = 0;
+           while (n < 50000) { n = n + 1; a = a + b * c; }
+           return a;
+         }
+         count(0, 2, 3);",
+    ),
+];
+
+/// Returns the instructions one run of `source` executes and the best time
+/// of `ROUNDS` runs.
+fn measure(source: &str, optimize: bool) -> (usize, Duration) {
+    let mut instructions = 0;
+    let elapsed = (0..ROUNDS)
+        .map(|_| {
+            let mut vm = Vm::new();
+            vm.set_optimize(optimize);
+            vm.set_stdout(Box::new(io::sink()));
+            let start = Instant::now();
+            vm.interpret(source).expect("workloads run without errors");
+            let elapsed = start.elapsed();
+            instructions = vm.instruction_count();
+            elapsed
+        })
+        .min()
+        .expect("ROUNDS is not zero");
+    (instructions, elapsed)
+}
+
+fn main() {
+    for (name, source) in WORKLOADS.iter() {
+        let (plain, plain_time) = measure(source, false);
+        let (optimized, optimized_time) = measure(source, true);
+        println!(
+            "{:<8} -O0 {:>9} instructions {:>10.2?}   -O1 {:>9} instructions {:>10.2?}   {:>5.1}% fewer",
+            name,
+            plain,
+            plain_time,
+            optimized,
+            optimized_time,
+            100.0 * (plain - optimized) as f64 / plain as f64
+        );
+    }
+}
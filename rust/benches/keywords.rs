@@ -0,0 +1,54 @@
+//! Measures what telling keywords from identifiers costs the scanner.
+//!
+//! Scans two sources of the same shape, one made of every keyword and one
+//! of identifiers with the same lengths that are not keywords, and reports
+//! the time per word of each. Run with `cargo bench --bench keywords`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const KEYWORDS: [&str; 21] = [
+    "and", "catch", "class", "else", "false", "finally", "for", "fun", "if", "in", "nil", "or",
+    "print", "return", "super", "this", "throw", "true", "try", "var", "while",
+];
+
+const REPEAT: usize = 2_000;
+const ROUNDS: usize = 20;
+
+/// Identifiers that share the first letters of a keyword but differ in the
+/// last one, so the trie has to walk as far as for the keyword itself.
+fn near_miss(keyword: &str) -> String {
+    let (head, _) = keyword.split_at(keyword.len() - 1);
+    format!("{}z", head)
+}
+
+/// Returns the best time of `ROUNDS` scans of `source`.
+fn time_scan(source: &str) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(lox::is_complete(black_box(source)));
+            start.elapsed()
+        })
+        .min()
+        .expect("ROUNDS is not zero")
+}
+
+fn main() {
+    let keywords = KEYWORDS.join(" ");
+    let identifiers: Vec<String> = KEYWORDS.iter().map(|keyword| near_miss(keyword)).collect();
+    let identifiers = identifiers.join(" ");
+    let words = (KEYWORDS.len() * REPEAT) as f64;
+
+    for (name, line) in &[("keywords", &keywords), ("identifiers", &identifiers)] {
+        let source = format!("{}\n", line).repeat(REPEAT);
+        let elapsed = time_scan(&source);
+        println!(
+            "{:<12} {:>8.1} ns/word ({} words in {:?})",
+            name,
+            elapsed.as_nanos() as f64 / words,
+            words,
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Runs the Lox programs under `benches/workloads` through the VM and
+//! reports, for each, the instructions it executes, the best wall time of
+//! a few runs and the heap allocations of one run.
+//!
+//! Run with `cargo bench --bench workloads`. Arguments after `--` pick the
+//! workloads whose names contain them, and `--json` prints one JSON object
+//! instead of the table, to keep results of different commits around:
+//! `cargo bench --bench workloads -- --json fib trees`.
+//!
+//! A run that fails or prints something other than the `// expect:`
+//! comments of its program aborts the benchmark, so a regression cannot
+//! pass for a speed-up. Lox has no classes yet; the method call and zoo
+//! workloads use maps of fields and functions as objects.
+
+use lox::{Capture, Vm};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 5;
+
+const WORKLOADS: [(&str, &str); 6] = [
+    ("fib", include_str!("workloads/fib.lox")),
+    ("binary_trees", include_str!("workloads/binary_trees.lox")),
+    (
+        "string_building",
+        include_str!("workloads/string_building.lox"),
+    ),
+    ("method_calls", include_str!("workloads/method_calls.lox")),
+    ("zoo", include_str!("workloads/zoo.lox")),
+    ("arithmetic", include_str!("workloads/arithmetic.lox")),
+];
+
+/// The system allocator, counting the allocations made through it.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+struct Report {
+    name: &'static str,
+    instructions: usize,
+    time: Duration,
+    allocations: usize,
+    allocated_bytes: usize,
+}
+
+/// The lines `source` must print, from its `// expect:` comments.
+fn expected_output(source: &str) -> String {
+    source
+        .lines()
+        .filter_map(|line| line.split_once("// expect: "))
+        .map(|(_, output)| format!("{}\n", output))
+        .collect()
+}
+
+/// Runs `source` `ROUNDS` times, each time in a new VM.
+fn measure(name: &'static str, source: &str) -> Report {
+    let expected = expected_output(source);
+    let mut report = Report {
+        name,
+        instructions: 0,
+        time: Duration::MAX,
+        allocations: 0,
+        allocated_bytes: 0,
+    };
+
+    for _ in 0..ROUNDS {
+        let mut vm = Vm::new();
+        let stdout = Capture::new();
+        vm.set_stdout(Box::new(stdout.clone()));
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        let start = Instant::now();
+        let result = vm.interpret(source);
+        let time = start.elapsed();
+
+        if let Err(error) = result {
+            panic!("workload {} failed: {}", name, error);
+        }
+        assert_eq!(stdout.contents(), expected, "workload {} printed", name);
+
+        report.instructions = vm.instruction_count();
+        report.time = report.time.min(time);
+        report.allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        report.allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes;
+    }
+    report
+}
+
+fn print_table(reports: &[Report]) {
+    println!(
+        "{:<16} {:>12} {:>12} {:>12} {:>14}",
+        "workload", "instructions", "time", "allocations", "bytes"
+    );
+    for report in reports {
+        println!(
+            "{:<16} {:>12} {:>12.2?} {:>12} {:>14}",
+            report.name,
+            report.instructions,
+            report.time,
+            report.allocations,
+            report.allocated_bytes
+        );
+    }
+}
+
+fn print_json(reports: &[Report]) {
+    let workloads: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            format!(
+                "{{\"name\": \"{}\", \"instructions\": {}, \"nanos\": {}, \"allocations\": {}, \"allocated_bytes\": {}}}",
+                report.name,
+                report.instructions,
+                report.time.as_nanos(),
+                report.allocations,
+                report.allocated_bytes
+            )
+        })
+        .collect();
+    println!(
+        "{{\"nan_boxing\": {}, \"rounds\": {}, \"workloads\": [\n  {}\n]}}",
+        cfg!(feature = "nan-boxing"),
+        ROUNDS,
+        workloads.join(",\n  ")
+    );
+}
+
+fn main() {
+    let mut json = false;
+    let mut filters: Vec<String> = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            // `cargo bench` passes this to every benchmark.
+            "--bench" => {}
+            _ => filters.push(arg),
+        }
+    }
+
+    let reports: Vec<Report> = WORKLOADS
+        .iter()
+        .filter(|(name, _)| filters.is_empty() || filters.iter().any(|f| name.contains(f)))
+        .map(|(name, source)| measure(name, source))
+        .collect();
+
+    if json {
+        print_json(&reports);
+    } else {
+        print_table(&reports);
+    }
+}
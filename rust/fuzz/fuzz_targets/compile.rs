@@ -0,0 +1,13 @@
+//! Compiles and optimizes arbitrary text. Compile errors are fine, panics
+//! are bugs.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    if let Ok(mut function) = lox::compile(&source) {
+        lox::optimize(&mut function);
+    }
+});
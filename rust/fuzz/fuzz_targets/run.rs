@@ -0,0 +1,28 @@
+//! Compiles and runs arbitrary text. Runtime errors are fine, panics are
+//! bugs. The limits keep endless loops and scripts that double a string
+//! in a loop from being reported as timeouts or running out of memory.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lox::Vm;
+use std::io;
+
+/// An endless loop stops after about a millisecond in a release build,
+/// which keeps the fuzzer at its usual speed.
+const INSTRUCTION_LIMIT: usize = 100_000;
+const ALLOCATION_LIMIT: usize = 1 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    let mut vm = Vm::new();
+    // The I/O natives stay disabled, fuzzing must not touch the file system.
+    vm.set_random_seed(0);
+    vm.set_instruction_limit(Some(INSTRUCTION_LIMIT));
+    vm.set_allocation_limit(Some(ALLOCATION_LIMIT));
+    vm.set_stdout(Box::new(io::sink()));
+    vm.set_stderr(Box::new(io::sink()));
+
+    let _ = vm.interpret(&source);
+    let _ = vm.disassemble(&mut io::sink());
+});
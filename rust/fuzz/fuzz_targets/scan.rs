@@ -0,0 +1,11 @@
+//! Scans arbitrary text to the end. `is_complete` asks the scanner for
+//! every token, so any input that makes it panic is a bug.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    lox::is_complete(&source);
+});
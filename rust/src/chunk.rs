@@ -4,12 +4,52 @@ use crate::value::{Value, ValueArray};
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum OpCode {
     OpConstant,
-    OpNegate,
+    OpNil,
+    OpTrue,
+    OpFalse,
+    OpPop,
+    OpGetLocal,
+    OpSetLocal,
+    OpGetGlobal,
+    OpDefineGlobal,
+    OpSetGlobal,
+    OpEqual,
+    OpGreater,
+    OpLess,
     OpAdd,
     OpSubtract,
     OpMultiply,
     OpDivide,
+    OpModulo,
+    OpNot,
+    OpNegate,
+    OpPrint,
+    OpJump,
+    OpJumpIfFalse,
+    OpLoop,
+    OpIterInit,
+    OpIterNext,
+    OpCall,
+    OpTry,
+    OpPopHandler,
+    OpThrow,
+    OpRethrow,
+    OpBuildList,
+    OpBuildMap,
+    OpBuildString,
+    OpIndexGet,
+    OpIndexSet,
     OpReturn,
+
+    // Superinstructions the optimizer fuses common sequences into.
+    OpGetLocal0,
+    OpGetLocal1,
+    OpGetLocal2,
+    OpGetLocal3,
+    OpAddConstant,
+    OpLessJumpIfFalse,
+    OpIncrementLocal,
+
     Reg(u8),
 }
 
@@ -34,7 +74,7 @@ impl Chunk {
         self.lines.add_line(line)
     }
 
-    pub fn add_constant(&mut self, value: Value) -> u8 {
+    pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.add(value);
         self.constants.len() - 1
     }
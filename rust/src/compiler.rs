@@ -1,22 +1,1547 @@
-use crate::scanner::Scanner;
+use crate::chunk::{Chunk, OpCode};
+use crate::object::Function;
+use crate::scanner::{Scanner, Token, TT};
+use crate::value::Value;
+use std::mem;
+use std::rc::Rc;
 
-pub fn compile(source: &str) {
-    let mut scanner = Scanner::new(&source);
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_EXACT_INTEGER: u64 = 1 << 53;
+/// How deeply statements and expressions may nest. The parser recurses
+/// once per level, so deeper code would overflow the stack.
+const MAX_NESTING: usize = 256;
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Comma,       // ,
+    Assignment,  // =
+    Conditional, // ?:
+    Or,          // or
+    And,         // and
+    Equality,    // == !=
+    Comparison,  // < > <= >=
+    Term,        // + -
+    Factor,      // * / %
+    Unary,       // ! -
+    Call,        // . () []
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Comma,
+            Precedence::Comma => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Conditional,
+            Precedence::Conditional => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn<'a> = fn(&mut Parser<'a>, bool);
+
+struct ParseRule<'a> {
+    prefix: Option<ParseFn<'a>>,
+    infix: Option<ParseFn<'a>>,
+    precedence: Precedence,
+}
+
+struct Local<'a> {
+    name: &'a str,
+    /// `None` while the initializer of the variable is being compiled.
+    depth: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FunctionType {
+    Function,
+    Script,
+}
+
+/// State of the function being compiled. Function declarations swap in a
+/// fresh one and restore the enclosing one when the body is done.
+struct Compiler<'a> {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local<'a>>,
+    scope_depth: usize,
+    /// The `return` statements inside each enclosing `try` statement, which
+    /// are errors once it turns out to have a `finally` clause.
+    returns_in_try: Vec<Vec<Token<'a>>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(function_type: FunctionType, name: Option<&str>) -> Compiler<'a> {
+        // Slot zero holds the function being called.
+        let callee = Local {
+            name: "",
+            depth: Some(0),
+        };
+        Compiler {
+            function: Function::new(name),
+            function_type,
+            locals: vec![callee],
+            scope_depth: 0,
+            returns_in_try: Vec::new(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    scanner: Scanner<'a>,
+    compiler: Compiler<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    errors: Vec<String>,
+    panic_mode: bool,
+    /// Number of `if`, `while` and `for` bodies around the current statement.
+    nesting: usize,
+    /// Number of statements and expressions around the current one.
+    depth: usize,
+}
+
+/// Compiles `source` into the function for the top level code of the
+/// script, or returns the reported compile errors.
+///
+/// The function returns the value of the last statement of the script when that
+/// is an expression statement, `nil` otherwise.
+pub fn compile(source: &str) -> Result<Function, Vec<String>> {
+    let mut parser = Parser::new(source);
+
+    parser.advance();
+    while !parser.match_token(TT::Eof) {
+        parser.declaration();
+    }
+    parser.end_compiler();
+
+    if parser.errors.is_empty() {
+        Ok(parser.compiler.function)
+    } else {
+        Err(parser.errors)
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Parser<'a> {
+        let empty = Token {
+            typ: TT::Eof,
+            data: "",
+            line: 0,
+        };
+        Parser {
+            scanner: Scanner::new(source),
+            compiler: Compiler::new(FunctionType::Script, None),
+            current: empty,
+            previous: empty,
+            errors: Vec::new(),
+            panic_mode: false,
+            nesting: 0,
+            depth: 0,
+        }
+    }
+
+    fn error_at(&mut self, token: Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+
+        let location = match token.typ {
+            TT::Eof => " at end".to_string(),
+            TT::Error => String::new(),
+            _ => format!(" at '{}'", token.data),
+        };
+        self.errors.push(format!(
+            "[line {}] Error{}: {}",
+            token.line, location, message
+        ));
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous, message);
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current;
+
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.typ != TT::Error {
+                break;
+            }
+            self.error_at_current(self.current.data);
+        }
+    }
+
+    fn consume(&mut self, typ: TT, message: &str) {
+        if self.current.typ == typ {
+            self.advance();
+            return;
+        }
+        self.error_at_current(message);
+    }
+
+    fn check(&self, typ: TT) -> bool {
+        self.current.typ == typ
+    }
+
+    fn match_token(&mut self, typ: TT) -> bool {
+        if !self.check(typ) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler.function.chunk
+    }
+
+    fn emit_byte(&mut self, byte: OpCode) {
+        let line = self.previous.line;
+        self.chunk().add_chunk(byte, line);
+    }
+
+    fn emit_bytes(&mut self, byte1: OpCode, byte2: OpCode) {
+        self.emit_byte(byte1);
+        self.emit_byte(byte2);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OpCode::OpLoop);
+
+        let offset = self.chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+        self.emit_byte(OpCode::Reg((offset >> 8) as u8));
+        self.emit_byte(OpCode::Reg(offset as u8));
+    }
+
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        self.emit_byte(instruction);
+        self.emit_byte(OpCode::Reg(0xff));
+        self.emit_byte(OpCode::Reg(0xff));
+        self.chunk().code.len() - 2
+    }
+
+    fn emit_return(&mut self) {
+        self.emit_byte(OpCode::OpNil);
+        self.emit_byte(OpCode::OpReturn);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let constant = self.chunk().add_constant(value);
+        if constant > u8::MAX as usize {
+            self.error("Too many constants in one chunk.");
+            return 0;
+        }
+        constant as u8
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_bytes(OpCode::OpConstant, OpCode::Reg(constant));
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+        self.chunk().code[offset] = OpCode::Reg((jump >> 8) as u8);
+        self.chunk().code[offset + 1] = OpCode::Reg(jump as u8);
+    }
+
+    fn end_compiler(&mut self) {
+        self.emit_return();
+    }
+
+    fn begin_scope(&mut self) {
+        self.compiler.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.compiler.scope_depth -= 1;
+
+        while let Some(local) = self.compiler.locals.last() {
+            if local
+                .depth
+                .is_none_or(|depth| depth <= self.compiler.scope_depth)
+            {
+                break;
+            }
+            self.emit_byte(OpCode::OpPop);
+            self.compiler.locals.pop();
+        }
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        if self.too_deep("Expression nesting too deep.") {
+            return;
+        }
+        self.advance();
+        let prefix_rule = match get_rule(self.previous.typ).prefix {
+            Some(rule) => rule,
+            None => {
+                self.error("Expect expression.");
+                return;
+            }
+        };
+
+        self.depth += 1;
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix_rule(self, can_assign);
+
+        while precedence <= get_rule(self.current.typ).precedence {
+            self.advance();
+            if let Some(infix_rule) = get_rule(self.previous.typ).infix {
+                infix_rule(self, can_assign);
+            }
+        }
+        self.depth -= 1;
+
+        if can_assign && self.match_token(TT::Equal) {
+            self.error("Invalid assignment target.");
+        }
+    }
+
+    /// Reports `message` when `MAX_NESTING` levels are already open, in
+    /// which case the caller must not go one level deeper.
+    fn too_deep(&mut self, message: &str) -> bool {
+        let too_deep = self.depth >= MAX_NESTING;
+        if too_deep {
+            self.error_at_current(message);
+        }
+        too_deep
+    }
+
+    fn identifier_constant(&mut self, name: Token) -> u8 {
+        self.make_constant(Value::from(name.data))
+    }
+
+    fn resolve_local(&mut self, name: Token) -> Option<u8> {
+        for (slot, local) in self.compiler.locals.iter().enumerate().rev() {
+            if local.name == name.data {
+                if local.depth.is_none() {
+                    self.error("Cannot read local variable in its own initializer.");
+                }
+                return Some(slot as u8);
+            }
+        }
+        None
+    }
+
+    fn add_local(&mut self, name: Token<'a>) {
+        if self.compiler.locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        self.compiler.locals.push(Local {
+            name: name.data,
+            depth: None,
+        });
+    }
+
+    fn declare_variable(&mut self) {
+        // Global variables are implicitly declared.
+        if self.compiler.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous;
+        let scope_depth = self.compiler.scope_depth;
+        let already_declared = self
+            .compiler
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= scope_depth))
+            .any(|local| local.name == name.data);
+        if already_declared {
+            self.error("Variable with this name already declared in this scope.");
+        }
+
+        self.add_local(name);
+    }
+
+    fn parse_variable(&mut self, message: &str) -> u8 {
+        self.consume(TT::Identifier, message);
+
+        self.declare_variable();
+        if self.compiler.scope_depth > 0 {
+            return 0;
+        }
+
+        self.identifier_constant(self.previous)
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.compiler.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = self.compiler.locals.last_mut() {
+            local.depth = Some(self.compiler.scope_depth);
+        }
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.compiler.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_bytes(OpCode::OpDefineGlobal, OpCode::Reg(global));
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+
+        self.emit_byte(OpCode::OpPop);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.check(TT::RightParen) {
+            loop {
+                self.parse_precedence(Precedence::Assignment);
+                if arg_count == u8::MAX as usize {
+                    self.error("Cannot have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.match_token(TT::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TT::RightParen, "Expect ')' after arguments.");
+        arg_count.min(u8::MAX as usize) as u8
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let operator_type = self.previous.typ;
+
+        // Compile the right operand.
+        let rule = get_rule(operator_type);
+        self.parse_precedence(rule.precedence.next());
+
+        match operator_type {
+            TT::BangEqual => self.emit_bytes(OpCode::OpEqual, OpCode::OpNot),
+            TT::EqualEqual => self.emit_byte(OpCode::OpEqual),
+            TT::Greater => self.emit_byte(OpCode::OpGreater),
+            TT::GreaterEqual => self.emit_bytes(OpCode::OpLess, OpCode::OpNot),
+            TT::Less => self.emit_byte(OpCode::OpLess),
+            TT::LessEqual => self.emit_bytes(OpCode::OpGreater, OpCode::OpNot),
+            TT::Plus => self.emit_byte(OpCode::OpAdd),
+            TT::Minus => self.emit_byte(OpCode::OpSubtract),
+            TT::Star => self.emit_byte(OpCode::OpMultiply),
+            TT::Slash => self.emit_byte(OpCode::OpDivide),
+            TT::Percent => self.emit_byte(OpCode::OpModulo),
+            _ => {}
+        }
+    }
+
+    /// `a, b` evaluates `a` for its side effects and yields `b`.
+    fn comma(&mut self, _can_assign: bool) {
+        self.emit_byte(OpCode::OpPop);
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    /// `cond ? a : b`. Both branches take a full expression, commas
+    /// included, which makes the operator right-associative.
+    fn conditional(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.emit_byte(OpCode::OpPop);
+        self.expression();
+        let end_jump = self.emit_jump(OpCode::OpJump);
+        self.consume(
+            TT::Colon,
+            "Expect ':' after then branch of conditional expression.",
+        );
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::OpPop);
+        self.expression();
+        self.patch_jump(end_jump);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.emit_bytes(OpCode::OpCall, OpCode::Reg(arg_count));
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TT::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.match_token(TT::Equal) {
+            self.parse_precedence(Precedence::Assignment);
+            self.emit_byte(OpCode::OpIndexSet);
+        } else {
+            self.emit_byte(OpCode::OpIndexGet);
+        }
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        while !self.check(TT::RightBracket) {
+            self.parse_precedence(Precedence::Assignment);
+            if count == u8::MAX as usize {
+                self.error("Cannot have more than 255 elements in a list literal.");
+            }
+            count += 1;
+            if !self.match_token(TT::Comma) {
+                break;
+            }
+        }
+        self.consume(TT::RightBracket, "Expect ']' after list elements.");
+        self.emit_bytes(
+            OpCode::OpBuildList,
+            OpCode::Reg(count.min(u8::MAX as usize) as u8),
+        );
+    }
+
+    /// A `{` in expression position starts a map; as a statement it starts
+    /// a block.
+    fn map(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        while !self.check(TT::RightBrace) {
+            self.parse_precedence(Precedence::Assignment);
+            self.consume(TT::Colon, "Expect ':' after map key.");
+            self.parse_precedence(Precedence::Assignment);
+            if count == u8::MAX as usize {
+                self.error("Cannot have more than 255 entries in a map literal.");
+            }
+            count += 1;
+            if !self.match_token(TT::Comma) {
+                break;
+            }
+        }
+        self.consume(TT::RightBrace, "Expect '}' after map entries.");
+        self.emit_bytes(
+            OpCode::OpBuildMap,
+            OpCode::Reg(count.min(u8::MAX as usize) as u8),
+        );
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.previous.typ {
+            TT::False => self.emit_byte(OpCode::OpFalse),
+            TT::Nil => self.emit_byte(OpCode::OpNil),
+            TT::True => self.emit_byte(OpCode::OpTrue),
+            _ => {}
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TT::RightParen, "Expect ')' after expression.");
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        match number_value(self.previous.data) {
+            Some(value) => self.emit_constant(Value::Number(value)),
+            None => self.error("Number literal is out of range."),
+        }
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::OpJump);
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::OpPop);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let data = self.previous.data;
+        if self.previous.typ == TT::RawString {
+            // Trim the `r`, the hashes and the quotes.
+            let hashes = data[1..].bytes().take_while(|&b| b == b'#').count();
+            let text = &data[hashes + 2..data.len() - hashes - 1];
+            self.emit_constant(Value::from(text));
+            return;
+        }
+
+        // The rest of an interpolated string after an empty `${}`.
+        if data.starts_with('}') {
+            self.error("Expect expression.");
+            return;
+        }
+
+        // Trim the surrounding quotes.
+        match unescape(&data[1..data.len() - 1]) {
+            Ok(text) => self.emit_constant(Value::from(text)),
+            Err(message) => self.error(&message),
+        }
+    }
+
+    /// Compiles `"a ${b} c"`: each literal part and each interpolated
+    /// expression is pushed, and `OP_BUILD_STRING` joins them into one
+    /// string. Literal parts that are empty are left out.
+    fn interpolation(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        loop {
+            // Trim the opening `"` or `}` and the `${`.
+            let data = self.previous.data;
+            count += self.string_part(&data[1..data.len() - 2]);
+            self.expression();
+            count += 1;
+            if !self.match_token(TT::Interpolation) {
+                break;
+            }
+        }
+        if self.match_token(TT::String) {
+            let data = self.previous.data;
+            count += self.string_part(&data[1..data.len() - 1]);
+        } else {
+            self.error_at_current("Expect '}' after interpolated expression.");
+        }
+
+        if count > u8::MAX as usize {
+            self.error("Cannot have more than 255 parts in an interpolated string.");
+        }
+        self.emit_bytes(
+            OpCode::OpBuildString,
+            OpCode::Reg(count.min(u8::MAX as usize) as u8),
+        );
+    }
+
+    /// Emits the unescaped `raw` text of an interpolated string's literal
+    /// part, unless it is empty. Returns the number of values pushed.
+    fn string_part(&mut self, raw: &str) -> usize {
+        match unescape(raw) {
+            Ok(text) if text.is_empty() => 0,
+            Ok(text) => {
+                self.emit_constant(Value::from(text));
+                1
+            }
+            Err(message) => {
+                self.error(&message);
+                0
+            }
+        }
+    }
+
+    fn named_variable(&mut self, name: Token, can_assign: bool) {
+        let (get_op, set_op, arg) = match self.resolve_local(name) {
+            Some(slot) => (OpCode::OpGetLocal, OpCode::OpSetLocal, slot),
+            None => {
+                let global = self.identifier_constant(name);
+                (OpCode::OpGetGlobal, OpCode::OpSetGlobal, global)
+            }
+        };
+
+        if can_assign && self.match_token(TT::Equal) {
+            self.parse_precedence(Precedence::Assignment);
+            self.emit_bytes(set_op, OpCode::Reg(arg));
+        } else {
+            self.emit_bytes(get_op, OpCode::Reg(arg));
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        self.named_variable(self.previous, can_assign);
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let operator_type = self.previous.typ;
+
+        // Compile the operand.
+        self.parse_precedence(Precedence::Unary);
+
+        match operator_type {
+            TT::Bang => self.emit_byte(OpCode::OpNot),
+            TT::Minus => self.emit_byte(OpCode::OpNegate),
+            _ => {}
+        }
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Comma);
+    }
+
+    fn block(&mut self) {
+        while !self.check(TT::RightBrace) && !self.check(TT::Eof) {
+            self.declaration();
+        }
+
+        self.consume(TT::RightBrace, "Expect '}' after block.");
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.previous.data;
+        let enclosing = mem::replace(&mut self.compiler, Compiler::new(function_type, Some(name)));
+        self.begin_scope();
+
+        // Compile the parameter list.
+        self.consume(TT::LeftParen, "Expect '(' after function name.");
+        if !self.check(TT::RightParen) {
+            loop {
+                self.compiler.function.arity += 1;
+                if self.compiler.function.arity > u8::MAX as usize {
+                    self.error_at_current("Cannot have more than 255 parameters.");
+                }
+
+                let param = self.parse_variable("Expect parameter name.");
+                self.define_variable(param);
+                if !self.match_token(TT::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TT::RightParen, "Expect ')' after parameters.");
+
+        // The body.
+        self.consume(TT::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        // Create the function object.
+        self.end_compiler();
+        let compiler = mem::replace(&mut self.compiler, enclosing);
+        self.emit_constant(Value::Function(Rc::new(compiler.function)));
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // A function may refer to itself in its body.
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+        self.var_initializer(global);
+    }
+
+    /// Compiles the rest of a `var` declaration after the variable name.
+    fn var_initializer(&mut self, global: u8) {
+        if self.match_token(TT::Equal) {
+            self.expression();
+        } else {
+            self.emit_byte(OpCode::OpNil);
+        }
+        self.consume(TT::Semicolon, "Expect ';' after variable declaration.");
+
+        self.define_variable(global);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TT::Semicolon, "Expect ';' after expression.");
+
+        // The last statement of the script hands its value to the caller.
+        if self.compiler.scope_depth == 0 && self.nesting == 0 && self.check(TT::Eof) {
+            self.emit_byte(OpCode::OpReturn);
+        } else {
+            self.emit_byte(OpCode::OpPop);
+        }
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TT::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TT::Semicolon) {
+            // No initializer.
+        } else if self.match_token(TT::Var) {
+            self.consume(TT::Identifier, "Expect variable name.");
+            let name = self.previous;
+            if self.match_token(TT::In) {
+                self.for_in_statement(name);
+                self.end_scope();
+                return;
+            }
+            // Variables of a for loop are always locals.
+            self.declare_variable();
+            self.var_initializer(0);
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TT::Semicolon) {
+            self.expression();
+            self.consume(TT::Semicolon, "Expect ';' after loop condition.");
+
+            // Jump out of the loop if the condition is false.
+            exit_jump = Some(self.emit_jump(OpCode::OpJumpIfFalse));
+            self.emit_byte(OpCode::OpPop); // Condition.
+        }
+
+        if !self.match_token(TT::RightParen) {
+            let body_jump = self.emit_jump(OpCode::OpJump);
+            let increment_start = self.chunk().code.len();
+            self.expression();
+            self.emit_byte(OpCode::OpPop);
+            self.consume(TT::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.body();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::OpPop); // Condition.
+        }
+
+        self.end_scope();
+    }
+
+    /// Compiles `for (var name in iterable) body` after the `in`. The
+    /// iterator lives in a hidden local below the loop variable.
+    fn for_in_statement(&mut self, name: Token<'a>) {
+        self.expression();
+        self.consume(TT::RightParen, "Expect ')' after for-in clause.");
+        self.emit_byte(OpCode::OpIterInit);
+        let hidden = Token { data: "", ..name };
+        self.add_local(hidden);
+        self.mark_initialized();
+
+        let loop_start = self.chunk().code.len();
+        let exit_jump = self.emit_jump(OpCode::OpIterNext);
+
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.body();
+        self.end_scope();
+
+        self.emit_loop(loop_start);
+        self.patch_jump(exit_jump);
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TT::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TT::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.emit_byte(OpCode::OpPop);
+        self.body();
+
+        let else_jump = self.emit_jump(OpCode::OpJump);
+
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::OpPop);
+
+        if self.match_token(TT::Else) {
+            self.body();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(TT::Semicolon, "Expect ';' after value.");
+        self.emit_byte(OpCode::OpPrint);
+    }
+
+    fn return_statement(&mut self) {
+        if self.compiler.function_type == FunctionType::Script {
+            self.error("Cannot return from top-level code.");
+        }
+        let keyword = self.previous;
+        if let Some(returns) = self.compiler.returns_in_try.last_mut() {
+            returns.push(keyword);
+        }
+
+        if self.match_token(TT::Semicolon) {
+            self.emit_return();
+        } else {
+            self.expression();
+            self.consume(TT::Semicolon, "Expect ';' after return value.");
+            self.emit_byte(OpCode::OpReturn);
+        }
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TT::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::OpThrow);
+    }
+
+    /// An exception inside the `try` block jumps to the `catch` handler. An
+    /// exception inside either jumps to the `finally` handler, which runs the
+    /// `finally` block and throws the exception again. Two hidden locals
+    /// tell the `finally` block which way it was entered: the exception and
+    /// whether there was one.
+    fn try_statement(&mut self) {
+        let finally_handler = self.emit_jump(OpCode::OpTry);
+        let catch_handler = self.emit_jump(OpCode::OpTry);
+        self.compiler.returns_in_try.push(Vec::new());
+
+        self.consume(TT::LeftBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.emit_byte(OpCode::OpPopHandler);
+
+        let has_catch = self.match_token(TT::Catch);
+        let after_catch = self.emit_jump(OpCode::OpJump);
+        self.patch_jump(catch_handler);
+        if has_catch {
+            self.begin_scope();
+            self.consume(TT::LeftParen, "Expect '(' after 'catch'.");
+            self.consume(TT::Identifier, "Expect exception variable name.");
+            self.add_local(self.previous);
+            self.mark_initialized();
+            self.consume(TT::RightParen, "Expect ')' after exception variable.");
+            self.consume(TT::LeftBrace, "Expect '{' after catch clause.");
+            self.block();
+            self.end_scope();
+        } else {
+            // Leave the exception to the finally handler.
+            self.emit_rethrow(self.compiler.locals.len());
+        }
+        self.patch_jump(after_catch);
+        self.emit_byte(OpCode::OpPopHandler);
+
+        let returns = self.compiler.returns_in_try.pop().unwrap_or_default();
+        if !self.match_token(TT::Finally) {
+            if !has_catch {
+                self.error_at_current("Expect 'catch' or 'finally' after try block.");
+            }
+            if let Some(enclosing) = self.compiler.returns_in_try.last_mut() {
+                enclosing.extend(returns);
+            }
+            // Without a finally clause the finally handler only rethrows.
+            let end_jump = self.emit_jump(OpCode::OpJump);
+            self.patch_jump(finally_handler);
+            self.emit_rethrow(self.compiler.locals.len());
+            self.patch_jump(end_jump);
+            return;
+        }
+        for keyword in returns {
+            self.error_at(
+                keyword,
+                "Cannot return from inside a 'try' with a 'finally' clause.",
+            );
+        }
+
+        // Completed normally: no exception.
+        self.emit_bytes(OpCode::OpNil, OpCode::OpFalse);
+        let finally_jump = self.emit_jump(OpCode::OpJump);
+        self.patch_jump(finally_handler);
+        self.emit_byte(OpCode::OpTrue);
+        self.patch_jump(finally_jump);
+
+        self.begin_scope();
+        let hidden = Token {
+            data: "",
+            ..self.previous
+        };
+        for _ in 0..2 {
+            self.add_local(hidden);
+            self.mark_initialized();
+        }
+        let exception_slot = (self.compiler.locals.len() - 2) as u8;
+        let thrown_slot = exception_slot + 1;
+
+        self.consume(TT::LeftBrace, "Expect '{' after 'finally'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+
+        self.emit_bytes(OpCode::OpGetLocal, OpCode::Reg(thrown_slot));
+        let done_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.emit_byte(OpCode::OpPop);
+        self.emit_rethrow(exception_slot as usize);
+        self.patch_jump(done_jump);
+        self.emit_byte(OpCode::OpPop);
+        self.end_scope();
+    }
+
+    /// Throws the exception a handler of the try statement caught, which
+    /// is in local `slot`, on with the stack trace of its first throw.
+    fn emit_rethrow(&mut self, slot: usize) {
+        self.emit_bytes(OpCode::OpRethrow, OpCode::Reg(slot as u8));
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.chunk().code.len();
+        self.consume(TT::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TT::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+
+        self.emit_byte(OpCode::OpPop);
+        self.body();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::OpPop);
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.current.typ != TT::Eof {
+            if self.previous.typ == TT::Semicolon {
+                return;
+            }
+
+            match self.current.typ {
+                TT::Class
+                | TT::Fun
+                | TT::Var
+                | TT::For
+                | TT::If
+                | TT::While
+                | TT::Print
+                | TT::Return
+                | TT::Throw
+                | TT::Try => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /// Skips the rest of the enclosing block, up to its closing brace,
+    /// without recursing into the blocks inside it.
+    fn skip_block(&mut self) {
+        let mut open = 0;
+        while self.current.typ != TT::Eof {
+            match self.current.typ {
+                TT::LeftBrace => open += 1,
+                TT::RightBrace if open == 0 => break,
+                TT::RightBrace => open -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+        self.panic_mode = false;
+    }
+
+    fn declaration(&mut self) {
+        if self.too_deep("Statement nesting too deep.") {
+            self.skip_block();
+            return;
+        }
+
+        self.depth += 1;
+        if self.match_token(TT::Fun) {
+            self.fun_declaration();
+        } else if self.match_token(TT::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+        self.depth -= 1;
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TT::Print) {
+            self.print_statement();
+        } else if self.match_token(TT::For) {
+            self.for_statement();
+        } else if self.match_token(TT::If) {
+            self.if_statement();
+        } else if self.match_token(TT::Return) {
+            self.return_statement();
+        } else if self.match_token(TT::Throw) {
+            self.throw_statement();
+        } else if self.match_token(TT::Try) {
+            self.try_statement();
+        } else if self.match_token(TT::While) {
+            self.while_statement();
+        } else if self.match_token(TT::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    /// Compiles the statement controlled by an `if`, `while` or `for`.
+    fn body(&mut self) {
+        if self.too_deep("Statement nesting too deep.") {
+            self.skip_block();
+            return;
+        }
+        self.nesting += 1;
+        self.depth += 1;
+        self.statement();
+        self.depth -= 1;
+        self.nesting -= 1;
+    }
+}
+
+/// The value of a number literal the scanner accepted, or `None` if it is
+/// out of range: a decimal literal that overflows to infinity, or a hex or
+/// binary one above 2^53, past which a `f64` skips integers.
+fn number_value(lexeme: &str) -> Option<f64> {
+    let digits = lexeme.replace('_', "");
+    let radix_value = |digits: &str, radix| {
+        u64::from_str_radix(digits, radix)
+            .ok()
+            .filter(|&value| value <= MAX_EXACT_INTEGER)
+            .map(|value| value as f64)
+    };
+
+    if let Some(hex) = digits.strip_prefix("0x") {
+        radix_value(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        radix_value(binary, 2)
+    } else {
+        digits.parse::<f64>().ok().filter(|value| value.is_finite())
+    }
+}
+
+/// Decodes the escape sequences of a string literal: `\n`, `\t`, `\"`,
+/// `\\`, `\$` and `\u{...}` with one to six hex digits.
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut text = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('"') => text.push('"'),
+            Some('\\') => text.push('\\'),
+            Some('$') => text.push('$'),
+            Some('u') => text.push(unicode_escape(&mut chars)?),
+            Some(other) => return Err(format!("Invalid escape sequence '\\{}'.", other)),
+            None => return Err("Invalid escape sequence '\\'.".to_string()),
+        }
+    }
+    Ok(text)
+}
+
+/// The character of a `\u{...}` escape, `chars` starts after the `u`.
+fn unicode_escape(chars: &mut std::str::Chars) -> Result<char, String> {
+    let invalid = || "Invalid unicode escape sequence.".to_string();
+
+    if chars.next() != Some('{') {
+        return Err(invalid());
+    }
+    let mut digits = String::new();
     loop {
-        let _token = scanner.scan_token();
-    }
-    //    int line = -1;
-    //    for (;;) {
-    //        Token token = scanToken();
-    //        if (token.line != line) {
-    //            printf("%4d ", token.line);
-    //            line = token.line;
-    //        } else {
-    //            printf("   | ");
-    //        }
-    //        printf("%2d '%.*s'\n", token.type, token.length, token.start); ​
-    //
-    //        if (token.type == TOKEN_EOF) break;
-    //    }
+        match chars.next() {
+            Some('}') => break,
+            Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+            _ => return Err(invalid()),
+        }
+    }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(invalid)
+}
+
+fn get_rule<'a>(typ: TT) -> ParseRule<'a> {
+    let (prefix, infix, precedence): (Option<ParseFn>, Option<ParseFn>, Precedence) = match typ {
+        TT::LeftParen => (Some(Parser::grouping), Some(Parser::call), Precedence::Call),
+        TT::LeftBrace => (Some(Parser::map), None, Precedence::None),
+        TT::LeftBracket => (Some(Parser::list), Some(Parser::index), Precedence::Call),
+        TT::Minus => (Some(Parser::unary), Some(Parser::binary), Precedence::Term),
+        TT::Plus => (None, Some(Parser::binary), Precedence::Term),
+        TT::Slash | TT::Star | TT::Percent => (None, Some(Parser::binary), Precedence::Factor),
+        TT::Bang => (Some(Parser::unary), None, Precedence::None),
+        TT::BangEqual | TT::EqualEqual => (None, Some(Parser::binary), Precedence::Equality),
+        TT::Greater | TT::GreaterEqual | TT::Less | TT::LessEqual => {
+            (None, Some(Parser::binary), Precedence::Comparison)
+        }
+        TT::Identifier => (Some(Parser::variable), None, Precedence::None),
+        TT::String | TT::RawString => (Some(Parser::string), None, Precedence::None),
+        TT::Interpolation => (Some(Parser::interpolation), None, Precedence::None),
+        TT::Number => (Some(Parser::number), None, Precedence::None),
+        TT::And => (None, Some(Parser::and), Precedence::And),
+        TT::Or => (None, Some(Parser::or), Precedence::Or),
+        TT::Question => (None, Some(Parser::conditional), Precedence::Conditional),
+        TT::Comma => (None, Some(Parser::comma), Precedence::Comma),
+        TT::False | TT::Nil | TT::True => (Some(Parser::literal), None, Precedence::None),
+        _ => (None, None, Precedence::None),
+    };
+    ParseRule {
+        prefix,
+        infix,
+        precedence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_expression() {
+        let chunk = compile("1 + 2 * 3;").unwrap().chunk;
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpConstant,
+                OpCode::Reg(1),
+                OpCode::OpConstant,
+                OpCode::Reg(2),
+                OpCode::OpMultiply,
+                OpCode::OpAdd,
+                OpCode::OpReturn,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_grouping_and_negate() {
+        let chunk = compile("print -(1 - 2);").unwrap().chunk;
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpConstant,
+                OpCode::Reg(1),
+                OpCode::OpSubtract,
+                OpCode::OpNegate,
+                OpCode::OpPrint,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_locals() {
+        let chunk = compile("{ var a = 1; a = a; }").unwrap().chunk;
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpGetLocal,
+                OpCode::Reg(1),
+                OpCode::OpSetLocal,
+                OpCode::Reg(1),
+                OpCode::OpPop,
+                OpCode::OpPop,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_jumps() {
+        let chunk = compile("if (true) 1; else 2;").unwrap().chunk;
+
+        assert_eq!(
+            &chunk.code[..4],
+            &[
+                OpCode::OpTrue,
+                OpCode::OpJumpIfFalse,
+                OpCode::Reg(0),
+                OpCode::Reg(7),
+            ]
+        );
+        assert_eq!(chunk.code[11], OpCode::OpPop);
+    }
+
+    #[test]
+    fn compile_conditional() {
+        let chunk = compile("true ? 1 : 2;").unwrap().chunk;
+
+        assert_eq!(
+            &chunk.code[..14],
+            &[
+                OpCode::OpTrue,
+                OpCode::OpJumpIfFalse,
+                OpCode::Reg(0),
+                OpCode::Reg(6),
+                OpCode::OpPop,
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpJump,
+                OpCode::Reg(0),
+                OpCode::Reg(3),
+                OpCode::OpPop,
+                OpCode::OpConstant,
+                OpCode::Reg(1),
+                OpCode::OpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_lists() {
+        let chunk = compile("[1, 2][0] = nil;").unwrap().chunk;
+
+        assert_eq!(
+            &chunk.code[..9],
+            &[
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpConstant,
+                OpCode::Reg(1),
+                OpCode::OpBuildList,
+                OpCode::Reg(2),
+                OpCode::OpConstant,
+                OpCode::Reg(2),
+                OpCode::OpNil,
+            ]
+        );
+        assert_eq!(chunk.code[9], OpCode::OpIndexSet);
+        assert_eq!(
+            compile("[1, 2;").unwrap_err(),
+            vec!["[line 1] Error at ';': Expect ']' after list elements."]
+        );
+    }
+
+    #[test]
+    fn compile_maps() {
+        let chunk = compile("var m = {\"a\": 1, 2: nil,};").unwrap().chunk;
+
+        assert_eq!(
+            &chunk.code[..7],
+            &[
+                OpCode::OpConstant,
+                OpCode::Reg(1),
+                OpCode::OpConstant,
+                OpCode::Reg(2),
+                OpCode::OpConstant,
+                OpCode::Reg(3),
+                OpCode::OpNil,
+            ]
+        );
+        assert_eq!(&chunk.code[7..9], &[OpCode::OpBuildMap, OpCode::Reg(2)]);
+        assert_eq!(
+            compile("print {1 2};").unwrap_err(),
+            vec!["[line 1] Error at '2': Expect ':' after map key."]
+        );
+    }
+
+    #[test]
+    fn compile_for_in() {
+        let chunk = compile("for (var x in y) print x;").unwrap().chunk;
+
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::OpGetGlobal,
+                OpCode::Reg(0),
+                OpCode::OpIterInit,
+                OpCode::OpIterNext,
+                OpCode::Reg(0),
+                OpCode::Reg(7),
+                OpCode::OpGetLocal,
+                OpCode::Reg(2),
+                OpCode::OpPrint,
+                OpCode::OpPop,
+                OpCode::OpLoop,
+                OpCode::Reg(0),
+                OpCode::Reg(10),
+                OpCode::OpPop,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+        assert_eq!(
+            compile("for (var x in y;) {}").unwrap_err()[0],
+            "[line 1] Error at ';': Expect ')' after for-in clause."
+        );
+    }
+
+    #[test]
+    fn compile_strings() {
+        let constants = |source: &str| {
+            let chunk = compile(source).unwrap().chunk;
+            (0..chunk.constants.len())
+                .map(|idx| chunk.constants.get(idx).to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            constants(r#""a\tb\n"; "\"q\" \\"; "\u{48}\u{e9}\u{1F600}";"#),
+            vec!["a\tb\n", "\"q\" \\", "Hé😀"]
+        );
+        assert_eq!(
+            constants(r##"r"\n"; r#"say "hi""#;"##),
+            vec!["\\n", "say \"hi\""]
+        );
+        assert_eq!(
+            compile("\n\"bad \\q\";").unwrap_err(),
+            vec!["[line 2] Error at '\"bad \\q\"': Invalid escape sequence '\\q'."]
+        );
+        for escape in &[
+            "\\u",
+            "\\u{}",
+            "\\u{110000}",
+            "\\u{1234567}",
+            "\\u{zz}",
+            "\\u{41",
+        ] {
+            let source = format!("\"{}\";", escape);
+            assert_eq!(
+                compile(&source).unwrap_err(),
+                vec![format!(
+                    "[line 1] Error at '\"{}\"': Invalid unicode escape sequence.",
+                    escape
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn compile_interpolation() {
+        let chunk = compile(r#""a ${b} c${d}";"#).unwrap().chunk;
+
+        assert_eq!(
+            &chunk.code[..11],
+            &[
+                OpCode::OpConstant,
+                OpCode::Reg(0),
+                OpCode::OpGetGlobal,
+                OpCode::Reg(1),
+                OpCode::OpConstant,
+                OpCode::Reg(2),
+                OpCode::OpGetGlobal,
+                OpCode::Reg(3),
+                OpCode::OpBuildString,
+                OpCode::Reg(4),
+                OpCode::OpReturn,
+            ]
+        );
+        assert_eq!(
+            compile(r#""${a b}";"#).unwrap_err(),
+            vec!["[line 1] Error at 'b': Expect '}' after interpolated expression."]
+        );
+        assert_eq!(
+            compile(r#""${}";"#).unwrap_err(),
+            vec!["[line 1] Error at '}\"': Expect expression."]
+        );
+        assert_eq!(
+            compile(r#""\q${a}";"#).unwrap_err(),
+            vec!["[line 1] Error at '\"\\q${': Invalid escape sequence '\\q'."]
+        );
+    }
+
+    #[test]
+    fn compile_numbers() {
+        let chunk = compile("0x1F; 0b1010; 1_000_000; 1.5e-3; 0x20_0000_0000_0000;")
+            .unwrap()
+            .chunk;
+        let constants: Vec<f64> = (0..chunk.constants.len())
+            .map(|idx| match chunk.constants.get(idx) {
+                Value::Number(value) => value,
+                _ => panic!("expected a number"),
+            })
+            .collect();
+
+        assert_eq!(constants, vec![31.0, 10.0, 1e6, 0.0015, 9007199254740992.0]);
+        for source in &["1e309;", "0x20_0000_0000_0001;", "0x1_0000_0000_0000_0000;"] {
+            assert_eq!(
+                compile(source).unwrap_err(),
+                vec![format!(
+                    "[line 1] Error at '{}': Number literal is out of range.",
+                    &source[..source.len() - 1]
+                )]
+            );
+        }
+        assert_eq!(
+            compile("print 1_;").unwrap_err(),
+            vec!["[line 1] Error: Expect digit after '_' in number."]
+        );
+    }
+
+    #[test]
+    fn compile_error() {
+        assert_eq!(
+            compile("1 +").unwrap_err(),
+            vec!["[line 1] Error at end: Expect expression."]
+        );
+        assert_eq!(
+            compile("(1").unwrap_err(),
+            vec!["[line 1] Error at end: Expect ')' after expression."]
+        );
+        assert_eq!(
+            compile("1 2;").unwrap_err(),
+            vec!["[line 1] Error at '2': Expect ';' after expression."]
+        );
+        assert_eq!(
+            compile("a * b = c;").unwrap_err(),
+            vec!["[line 1] Error at '=': Invalid assignment target."]
+        );
+        assert_eq!(
+            compile("{ var a = 1; var a = 2; }").unwrap_err(),
+            vec!["[line 1] Error at 'a': Variable with this name already declared in this scope."]
+        );
+        assert_eq!(
+            compile("true ? 1;").unwrap_err(),
+            vec!["[line 1] Error at ';': Expect ':' after then branch of conditional expression."]
+        );
+    }
+
+    #[test]
+    fn report_errors_after_synchronize() {
+        assert_eq!(compile("var = 1;\nprint;\nprint 1;").unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn limit_nesting() {
+        let nested = |open: &str, close: &str, depth: usize| {
+            format!("{}1{}", open.repeat(depth), close.repeat(depth))
+        };
+        assert!(compile(&format!("print {};", nested("(", ")", 200))).is_ok());
+        assert!(compile(&format!("{};", nested("-", "", 200))).is_ok());
+        assert!(compile(&format!("{}print 1;{}", "{ ".repeat(100), " }".repeat(100))).is_ok());
+
+        let expression = vec!["[line 1] Error at '(': Expression nesting too deep."];
+        let source = format!("print {};", nested("(", ")", 50_000));
+        assert_eq!(compile(&source).unwrap_err(), expression);
+        let source = format!("print {};\nprint 1;\nprint;", nested("[", "]", 50_000));
+        assert_eq!(compile(&source).unwrap_err().len(), 2);
+
+        let statement = vec!["[line 1] Error at '{': Statement nesting too deep."];
+        let source = format!("{}print 1;{}", "{".repeat(50_000), "}".repeat(50_000));
+        assert_eq!(compile(&source).unwrap_err(), statement);
+        let source = format!("{}print 1;", "if (true) ".repeat(50_000));
+        assert_eq!(
+            compile(&source).unwrap_err(),
+            vec!["[line 1] Error at 'true': Expression nesting too deep."]
+        );
+        let source = "fun f() { ".repeat(50_000);
+        assert!(compile(&source).is_err());
+    }
 }
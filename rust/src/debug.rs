@@ -1,50 +1,192 @@
 use crate::chunk::{Chunk, OpCode};
-use crate::value::print_value;
+use crate::object::Function;
+use crate::value::Value;
+use std::io;
+use std::io::Write;
 
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
+pub fn disassemble_chunk(out: &mut dyn Write, chunk: &Chunk, name: &str) -> io::Result<()> {
+    writeln!(out, "== {} ==", name)?;
 
     let mut offset: usize = 0;
     while offset < chunk.code.len() {
-        offset = disassemble_instruction(chunk, offset);
+        offset = disassemble_instruction(out, chunk, offset)?;
     }
+    Ok(())
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-    print!("{number:>0width$}", number = offset, width = 4);
+/// Disassembles `function` followed by every function declared inside it.
+pub fn disassemble_function(out: &mut dyn Write, function: &Function) -> io::Result<()> {
+    disassemble_chunk(out, &function.chunk, function.name())?;
+    for idx in 0..function.chunk.constants.len() {
+        if let Value::Function(inner) = function.chunk.constants.get(idx) {
+            writeln!(out)?;
+            disassemble_function(out, &inner)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn disassemble_instruction(
+    out: &mut dyn Write,
+    chunk: &Chunk,
+    offset: usize,
+) -> io::Result<usize> {
+    write!(out, "{number:>0width$}", number = offset, width = 4)?;
     let current_line = chunk.lines.get_line(offset);
     if offset > 0 && current_line == chunk.lines.get_line(offset - 1) {
-        print!("   | ");
+        write!(out, "   |")?;
     } else {
-        print!("{:4}", current_line);
+        write!(out, "{:4}", current_line)?;
     }
+    let (text, next) = instruction(chunk, offset);
+    writeln!(out, " {}", text)?;
+    Ok(next)
+}
+
+/// Renders the instruction at `offset` without the offset and line columns
+/// and returns it together with the offset of the next instruction.
+pub fn instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
     let instruction = &chunk.code[offset];
     match instruction {
-        OpCode::OpReturn => return simple_instruction("OP_RETURN", offset),
-        OpCode::OpAdd => return simple_instruction("OP_ADD", offset),
-        OpCode::OpSubtract => return simple_instruction("OP_SUBTRACT", offset),
-        OpCode::OpMultiply => return simple_instruction("OP_MULTIPLY", offset),
-        OpCode::OpDivide => return simple_instruction("OP_DIVIDE", offset),
-        OpCode::OpNegate => return simple_instruction("OP_NEGATE", offset),
-        OpCode::OpConstant => return constant_instruction("OP_CONSTANT", chunk, offset),
+        OpCode::OpConstant => constant_instruction("OP_CONSTANT", chunk, offset),
+        OpCode::OpNil => simple_instruction("OP_NIL", offset),
+        OpCode::OpTrue => simple_instruction("OP_TRUE", offset),
+        OpCode::OpFalse => simple_instruction("OP_FALSE", offset),
+        OpCode::OpPop => simple_instruction("OP_POP", offset),
+        OpCode::OpGetLocal => byte_instruction("OP_GET_LOCAL", chunk, offset),
+        OpCode::OpSetLocal => byte_instruction("OP_SET_LOCAL", chunk, offset),
+        OpCode::OpGetGlobal => constant_instruction("OP_GET_GLOBAL", chunk, offset),
+        OpCode::OpDefineGlobal => constant_instruction("OP_DEFINE_GLOBAL", chunk, offset),
+        OpCode::OpSetGlobal => constant_instruction("OP_SET_GLOBAL", chunk, offset),
+        OpCode::OpEqual => simple_instruction("OP_EQUAL", offset),
+        OpCode::OpGreater => simple_instruction("OP_GREATER", offset),
+        OpCode::OpLess => simple_instruction("OP_LESS", offset),
+        OpCode::OpAdd => simple_instruction("OP_ADD", offset),
+        OpCode::OpSubtract => simple_instruction("OP_SUBTRACT", offset),
+        OpCode::OpMultiply => simple_instruction("OP_MULTIPLY", offset),
+        OpCode::OpDivide => simple_instruction("OP_DIVIDE", offset),
+        OpCode::OpModulo => simple_instruction("OP_MODULO", offset),
+        OpCode::OpNot => simple_instruction("OP_NOT", offset),
+        OpCode::OpNegate => simple_instruction("OP_NEGATE", offset),
+        OpCode::OpPrint => simple_instruction("OP_PRINT", offset),
+        OpCode::OpJump => jump_instruction("OP_JUMP", true, chunk, offset),
+        OpCode::OpJumpIfFalse => jump_instruction("OP_JUMP_IF_FALSE", true, chunk, offset),
+        OpCode::OpLoop => jump_instruction("OP_LOOP", false, chunk, offset),
+        OpCode::OpIterInit => simple_instruction("OP_ITER_INIT", offset),
+        OpCode::OpIterNext => jump_instruction("OP_ITER_NEXT", true, chunk, offset),
+        OpCode::OpCall => byte_instruction("OP_CALL", chunk, offset),
+        OpCode::OpTry => jump_instruction("OP_TRY", true, chunk, offset),
+        OpCode::OpPopHandler => simple_instruction("OP_POP_HANDLER", offset),
+        OpCode::OpThrow => simple_instruction("OP_THROW", offset),
+        OpCode::OpRethrow => byte_instruction("OP_RETHROW", chunk, offset),
+        OpCode::OpBuildList => byte_instruction("OP_BUILD_LIST", chunk, offset),
+        OpCode::OpBuildMap => byte_instruction("OP_BUILD_MAP", chunk, offset),
+        OpCode::OpBuildString => byte_instruction("OP_BUILD_STRING", chunk, offset),
+        OpCode::OpIndexGet => simple_instruction("OP_INDEX_GET", offset),
+        OpCode::OpIndexSet => simple_instruction("OP_INDEX_SET", offset),
+        OpCode::OpReturn => simple_instruction("OP_RETURN", offset),
+        OpCode::OpGetLocal0 => simple_instruction("OP_GET_LOCAL_0", offset),
+        OpCode::OpGetLocal1 => simple_instruction("OP_GET_LOCAL_1", offset),
+        OpCode::OpGetLocal2 => simple_instruction("OP_GET_LOCAL_2", offset),
+        OpCode::OpGetLocal3 => simple_instruction("OP_GET_LOCAL_3", offset),
+        OpCode::OpAddConstant => constant_instruction("OP_ADD_CONSTANT", chunk, offset),
+        OpCode::OpLessJumpIfFalse => jump_instruction("OP_LESS_JUMP_IF_FALSE", true, chunk, offset),
+        OpCode::OpIncrementLocal => byte_instruction("OP_INCREMENT_LOCAL", chunk, offset),
         OpCode::Reg(_) => panic!("Invalid opCode"),
     }
 }
 
-fn simple_instruction(name: &str, offset: usize) -> usize {
-    println!(" {}", name);
-    offset + 1
+fn register(chunk: &Chunk, offset: usize) -> u8 {
+    match chunk.code[offset] {
+        OpCode::Reg(val) => val,
+        _ => panic!("Invalid item"),
+    }
+}
+
+fn simple_instruction(name: &str, offset: usize) -> (String, usize) {
+    (name.to_string(), offset + 1)
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    let constant = chunk.code[offset + 1];
-    match constant {
-        OpCode::Reg(val) => {
-            print!(" {:<16} {:4} ", name, val);
-            print_value(chunk.constants[val as usize]);
-        }
-        _ => panic!("Invalid item"),
+fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let slot = register(chunk, offset + 1);
+    (format!("{:<16} {:4}", name, slot), offset + 2)
+}
+
+fn jump_instruction(name: &str, forward: bool, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let jump = (register(chunk, offset + 1) as usize) << 8 | register(chunk, offset + 2) as usize;
+    let target = if forward {
+        offset + 3 + jump
+    } else {
+        offset + 3 - jump
+    };
+    (
+        format!("{:<16} {:4} -> {}", name, offset, target),
+        offset + 3,
+    )
+}
+
+fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> (String, usize) {
+    let constant = register(chunk, offset + 1);
+    let text = format!(
+        "{:<16} {:4} {}",
+        name,
+        constant,
+        chunk.constants.get(constant as usize)
+    );
+    (text, offset + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    #[test]
+    fn disassemble() {
+        let chunk = compile("1 +\n-2;").unwrap().chunk;
+        let mut out = Vec::new();
+        disassemble_chunk(&mut out, &chunk, "code").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "== code ==\n\
+             0000   1 OP_CONSTANT         0 1\n\
+             0002   2 OP_CONSTANT         1 2\n\
+             0004   | OP_NEGATE\n\
+             0005   | OP_ADD\n\
+             0006   | OP_RETURN\n\
+             0007   | OP_NIL\n\
+             0008   | OP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn disassemble_jumps() {
+        let chunk = compile("while (false) {}").unwrap().chunk;
+        let mut out = Vec::new();
+        disassemble_chunk(&mut out, &chunk, "code").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "== code ==\n\
+             0000   1 OP_FALSE\n\
+             0001   | OP_JUMP_IF_FALSE    1 -> 8\n\
+             0004   | OP_POP\n\
+             0005   | OP_LOOP             5 -> 0\n\
+             0008   | OP_POP\n\
+             0009   | OP_NIL\n\
+             0010   | OP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn disassemble_nested_functions() {
+        let function = compile("fun f() { return 1; }").unwrap();
+        let mut out = Vec::new();
+        disassemble_function(&mut out, &function).unwrap();
+
+        let listing = String::from_utf8(out).unwrap();
+        assert!(listing.starts_with("== script ==\n0000   1 OP_CONSTANT         1 <fn f>\n"));
+        assert!(listing.contains("\n\n== f ==\n0000   1 OP_CONSTANT         0 1\n"));
     }
-    println!();
-    offset + 2
 }
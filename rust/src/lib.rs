@@ -0,0 +1,38 @@
+//! A bytecode virtual machine for the Lox language.
+//!
+//! ```
+//! use lox::{Value, Vm};
+//!
+//! let mut vm = Vm::new();
+//! vm.set_global("width", 6.0);
+//! let area = vm.interpret("var height = 7; width * height;").unwrap();
+//!
+//! assert_eq!(area, Value::Number(42.0));
+//! assert_eq!(vm.get_global("height"), Some(Value::Number(7.0)));
+//! ```
+
+mod chunk;
+mod compiler;
+mod debug;
+mod line_number;
+#[cfg(feature = "nan-boxing")]
+mod nanbox;
+mod natives;
+mod object;
+mod optimizer;
+mod output;
+mod scanner;
+mod stack;
+mod table;
+mod trace;
+mod value;
+mod vm;
+
+pub use crate::compiler::compile;
+pub use crate::object::{Function, LoxString, Native, NativeFn};
+pub use crate::optimizer::optimize;
+pub use crate::output::Capture;
+pub use crate::scanner::is_complete;
+pub use crate::trace::Trace;
+pub use crate::value::Value;
+pub use crate::vm::{LoxError, RuntimeError, Vm};
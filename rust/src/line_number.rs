@@ -14,47 +14,26 @@ impl LineNumber {
         LineNumber { list: Vec::new() }
     }
 
+    /// Records the line of the next byte of the chunk. Lines usually only
+    /// grow, but any order is accepted; a change of line starts a new run.
     pub fn add_line(&mut self, line: usize) {
-        let current_line = self.current_line();
-        let list = &mut self.list;
-        let list_len = list.len();
-
-        if line <= 0 {
-            panic!("Line number must be bigger then 0")
-        }
-
-        if current_line > line {
-            panic!("Line number should monotonically increasing number")
-        }
-
-        if list_len == 0 || current_line != line {
-            list.push(LineNumberItem { line, count: 1 })
-        } else {
-            let item = &mut list[list_len - 1];
-            item.count += 1;
+        match self.list.last_mut() {
+            Some(item) if item.line == line => item.count += 1,
+            _ => self.list.push(LineNumberItem { line, count: 1 }),
         }
     }
 
+    /// The line of the byte at `chunk_idx`. Past the end of the chunk this
+    /// is the last line recorded, and 0 when nothing was recorded.
     pub fn get_line(&self, chunk_idx: usize) -> usize {
-        let list = &self.list;
-        if list.len() == 0 {
-            return 0;
-        }
-        let mut i = 0;
-        let mut c = list[0].count - 1;
-        while c < chunk_idx && i < list.len() {
-            i = i + 1;
-            c = c + list[i].count;
-        }
-        list[i].line
-    }
-
-    fn current_line(&self) -> usize {
-        let list = &self.list;
-        match list.len() {
-            0 => 0,
-            n => list[n - 1].line,
+        let mut remaining = chunk_idx;
+        for item in &self.list {
+            if remaining < item.count {
+                return item.line;
+            }
+            remaining -= item.count;
         }
+        self.list.last().map_or(0, |item| item.line)
     }
 }
 
@@ -69,18 +48,25 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Line number must be bigger then 0")]
-    fn test_add_line_zero() {
+    fn test_add_line_in_random_order() {
         let mut ln = LineNumber::new();
+        ln.add_line(10);
+        ln.add_line(1);
         ln.add_line(0);
+
+        assert_eq!(ln.get_line(0), 10);
+        assert_eq!(ln.get_line(1), 1);
+        assert_eq!(ln.get_line(2), 0);
     }
 
     #[test]
-    #[should_panic(expected = "Line number should monotonically increasing number")]
-    fn test_add_line_in_random_order() {
+    fn test_get_line_past_the_end() {
         let mut ln = LineNumber::new();
-        ln.add_line(10);
         ln.add_line(1);
+        ln.add_line(2);
+
+        assert_eq!(ln.get_line(2), 2);
+        assert_eq!(ln.get_line(usize::MAX), 2);
     }
 
     #[test]
@@ -1,62 +1,148 @@
-mod chunk;
-mod compiler;
-mod debug;
-mod line_number;
-mod scanner;
-mod stack;
-mod value;
-mod vm;
-use self::chunk::Chunk;
-use self::vm::VM;
-use crate::vm::InterpretResult;
-use std::io::Write;
+mod repl;
+
+use lox::{LoxError, Trace, Vm};
+use std::ops::RangeInclusive;
 use std::{env, fs, io};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+const USAGE: &str = "Usage: lox [--trace] [--trace-function=<name>] [--trace-lines=<from>-<to>] \
+                     [--seed=<n>] [--ieee-division] [-O0|-O1] [--disassemble] [path]";
+
+#[derive(Debug, Default)]
+struct Options {
+    trace: bool,
+    trace_function: Option<String>,
+    trace_lines: Option<RangeInclusive<usize>>,
+    seed: Option<u64>,
+    ieee_division: bool,
+    /// `-O0` turns the peephole optimizer off, `-O1` (the default) on.
+    no_optimize: bool,
+    /// Print the compiled code instead of running it.
+    disassemble: bool,
+    path: Option<String>,
+}
 
-    match args.len() {
-        1 => repl(),
-        2 => run_file(&args[1]),
-        _ => {
-            eprintln!("Usage: clox [path]");
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
             std::process::exit(64)
         }
+    };
+
+    match &options.path {
+        None => repl::repl(&|| new_vm(&options)),
+        Some(path) => run_file(new_vm(&options), path, options.disassemble),
     }
 }
 
-fn repl() {
-    loop {
-        let line = get_input("> ");
-        if line.is_empty() {
-            return println!("Bye!");
+fn parse_args(args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut options = Options::default();
+
+    for arg in args {
+        if arg == "--trace" {
+            options.trace = true;
+        } else if let Some(name) = arg.strip_prefix("--trace-function=") {
+            options.trace = true;
+            options.trace_function = Some(name.to_string());
+        } else if let Some(range) = arg.strip_prefix("--trace-lines=") {
+            let (from, to) = range.split_once('-')?;
+            options.trace = true;
+            options.trace_lines = Some(from.parse().ok()?..=to.parse().ok()?);
+        } else if let Some(seed) = arg.strip_prefix("--seed=") {
+            options.seed = Some(seed.parse().ok()?);
+        } else if arg == "--ieee-division" {
+            options.ieee_division = true;
+        } else if arg == "-O0" || arg == "-O1" {
+            options.no_optimize = arg == "-O0";
+        } else if arg == "--disassemble" {
+            options.disassemble = true;
+        } else if arg.starts_with("--") || options.path.is_some() {
+            return None;
         } else {
-            println!("> {}", line)
+            options.path = Some(arg);
+        }
+    }
+    Some(options)
+}
+
+fn new_vm(options: &Options) -> Vm {
+    let mut vm = Vm::new();
+    // Scripts run from the command line are trusted.
+    vm.set_io_enabled(true);
+    if options.trace {
+        let mut trace = Trace::new(Box::new(io::stderr()));
+        if let Some(name) = &options.trace_function {
+            trace = trace.function(name);
         }
+        if let Some(lines) = &options.trace_lines {
+            trace = trace.lines(lines.clone());
+        }
+        vm.set_trace(Some(trace));
+    }
+    if let Some(seed) = options.seed {
+        vm.set_random_seed(seed);
     }
+    vm.set_ieee_division(options.ieee_division);
+    vm.set_optimize(!options.no_optimize);
+    vm
 }
 
-fn run_file(file_path: &str) {
-    let source = fs::read_to_string(file_path).expect("Something went wrong reading the file");
+fn run_file(mut vm: Vm, file_path: &str, disassemble: bool) {
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Could not read file \"{}\": {}", file_path, err);
+            std::process::exit(74)
+        }
+    };
 
-    let chunk = Chunk::new();
-    let mut vm = VM::new(chunk);
-    let result = vm.interpret(&source[..]);
+    if disassemble {
+        match vm.compile(&source) {
+            Ok(_) => {
+                let _ = vm.disassemble(&mut io::stdout());
+                std::process::exit(0)
+            }
+            Err(_) => std::process::exit(65),
+        }
+    }
 
-    match result {
-        InterpretResult::InterpretOk => std::process::exit(0),
-        InterpretResult::InterpretCompileError => std::process::exit(65),
-        InterpretResult::InterpretRuntimeError => std::process::exit(70),
+    match vm.interpret(&source[..]) {
+        Ok(_) => std::process::exit(0),
+        Err(LoxError::Compile(_)) => std::process::exit(65),
+        Err(LoxError::Runtime(_)) => std::process::exit(70),
     }
 }
 
-fn get_input(prompt: &str) -> String {
-    print!("{}", prompt);
-    io::stdout().flush();
-    let mut input = String::new();
-    match io::stdin().read_line(&mut input) {
-        Ok(_goes_into_input_above) => {}
-        Err(_no_updates_is_fine) => {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Option<Options> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_trace_flags() {
+        let options = parse(&["--trace-function=script", "--trace-lines=2-4", "a.lox"]).unwrap();
+
+        assert!(options.trace);
+        assert_eq!(options.trace_function, Some("script".to_string()));
+        assert_eq!(options.trace_lines, Some(2..=4));
+        assert_eq!(options.path, Some("a.lox".to_string()));
+        assert_eq!(parse(&["--seed=42"]).unwrap().seed, Some(42));
+        assert!(parse(&["--ieee-division"]).unwrap().ieee_division);
+        assert!(!parse(&["a.lox"]).unwrap().no_optimize);
+        assert!(parse(&["-O1", "-O0"]).unwrap().no_optimize);
+        assert!(!parse(&["-O0", "-O1"]).unwrap().no_optimize);
+        assert!(parse(&["--disassemble"]).unwrap().disassemble);
+    }
+
+    #[test]
+    fn parse_invalid_args() {
+        assert!(parse(&["a.lox", "b.lox"]).is_none());
+        assert!(parse(&["--trace-lines=2"]).is_none());
+        assert!(parse(&["--verbose"]).is_none());
+        assert!(parse(&["--seed=x"]).is_none());
     }
-    input.trim().to_string()
 }
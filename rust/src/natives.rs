@@ -0,0 +1,185 @@
+mod io;
+mod lists;
+mod maps;
+mod math;
+mod strings;
+
+use crate::object::Iter;
+use crate::value::{List, Map, Value};
+use crate::vm::{RuntimeError, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use self::math::Random;
+
+/// Defines the native functions every VM starts with.
+pub fn define_globals(vm: &mut Vm) {
+    vm.define_native("clock", 0, clock);
+    vm.define_native("len", 1, len);
+    vm.define_native("range", 3, range);
+    io::define(vm);
+    lists::define(vm);
+    maps::define(vm);
+    math::define(vm);
+    strings::define(vm);
+}
+
+/// Seconds since the Unix epoch. Only differences between two calls are
+/// meaningful, which is what benchmarks need.
+fn clock(_vm: &mut Vm, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::new("System clock is set before 1970."))?;
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+/// The number of characters of a string, elements of a list or entries of
+/// a map.
+fn len(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match &args[0] {
+        Value::String(string) => string.chars().count(),
+        Value::List(list) => list.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        _ => {
+            return Err(RuntimeError::new(
+                "Argument 1 of 'len' must be a string, a list or a map.",
+            ))
+        }
+    };
+    Ok(Value::Number(len as f64))
+}
+
+/// `range(start, end, step)` counts from `start` by `step` up to, but not
+/// including, `end`. A negative `step` counts down.
+fn range(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let start = number_arg("range", args, 0)?;
+    let end = number_arg("range", args, 1)?;
+    let step = number_arg("range", args, 2)?;
+
+    if step == 0.0 || step.is_nan() {
+        return Err(RuntimeError::new("Range step cannot be zero."));
+    }
+    Ok(Value::from(Iter::Range {
+        next: start,
+        end,
+        step,
+    }))
+}
+
+/// Argument `idx` of the native `name`, which must be a string.
+fn string_arg<'v>(name: &str, args: &'v [Value], idx: usize) -> Result<&'v str, RuntimeError> {
+    match &args[idx] {
+        Value::String(string) => Ok(string),
+        _ => Err(RuntimeError::new(format!(
+            "Argument {} of '{}' must be a string.",
+            idx + 1,
+            name
+        ))),
+    }
+}
+
+/// Argument `idx` of the native `name`, which must be a number.
+fn number_arg(name: &str, args: &[Value], idx: usize) -> Result<f64, RuntimeError> {
+    match args[idx] {
+        Value::Number(number) => Ok(number),
+        _ => Err(RuntimeError::new(format!(
+            "Argument {} of '{}' must be a number.",
+            idx + 1,
+            name
+        ))),
+    }
+}
+
+/// Argument `idx` of the native `name`, which must be a list.
+fn list_arg<'v>(
+    name: &str,
+    args: &'v [Value],
+    idx: usize,
+) -> Result<&'v Rc<RefCell<List>>, RuntimeError> {
+    match &args[idx] {
+        Value::List(list) => Ok(list),
+        _ => Err(RuntimeError::new(format!(
+            "Argument {} of '{}' must be a list.",
+            idx + 1,
+            name
+        ))),
+    }
+}
+
+/// Argument `idx` of the native `name`, which must be a map.
+fn map_arg<'v>(
+    name: &str,
+    args: &'v [Value],
+    idx: usize,
+) -> Result<&'v Rc<RefCell<Map>>, RuntimeError> {
+    match &args[idx] {
+        Value::Map(map) => Ok(map),
+        _ => Err(RuntimeError::new(format!(
+            "Argument {} of '{}' must be a map.",
+            idx + 1,
+            name
+        ))),
+    }
+}
+
+/// Argument `idx` of the native `name` as a position or length.
+fn index_arg(name: &str, args: &[Value], idx: usize) -> Result<usize, RuntimeError> {
+    let number = number_arg(name, args, idx)?;
+    if number < 0.0 || number.fract() != 0.0 || number > u32::MAX as f64 {
+        return Err(RuntimeError::new(format!(
+            "Argument {} of '{}' must be a non-negative integer.",
+            idx + 1,
+            name
+        )));
+    }
+    Ok(number as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+    use crate::vm::LoxError;
+
+    /// Runs `source` in a new VM that keeps its error reports to itself.
+    pub(super) fn eval(source: &str) -> Result<Value, LoxError> {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+        vm.interpret(source)
+    }
+
+    /// The value `source` ends with, as `print` shows it.
+    pub(super) fn show(source: &str) -> String {
+        eval(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn ranges() {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+        let sum = "var sum = 0; for (var i in r) sum = sum + i; sum;";
+        let mut sum_of = |range: &str| vm.interpret(&format!("var r = {}; {}", range, sum));
+
+        assert_eq!(sum_of("range(0, 5, 1)"), Ok(Value::Number(10.0)));
+        assert_eq!(sum_of("range(0, 5, 2)"), Ok(Value::Number(6.0)));
+        assert_eq!(sum_of("range(5, 0, -2)"), Ok(Value::Number(9.0)));
+        assert_eq!(sum_of("range(5, 5, 1)"), Ok(Value::Number(0.0)));
+        assert!(sum_of("range(0, 5, 0)").is_err());
+        assert_eq!(
+            vm.interpret("range(0, 1, 1);").unwrap().to_string(),
+            "<iterator>"
+        );
+    }
+
+    #[test]
+    fn clock_advances() {
+        let mut vm = Vm::new();
+        let result = vm.interpret("var start = clock(); clock() - start;");
+
+        match result {
+            Ok(Value::Number(elapsed)) => assert!(elapsed >= 0.0),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use super::string_arg;
+use crate::value::Value;
+use crate::vm::{RuntimeError, Vm};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+pub fn define(vm: &mut Vm) {
+    vm.define_native("readFile", 1, read_file);
+    vm.define_native("writeFile", 2, write_file);
+    vm.define_native("appendFile", 2, append_file);
+    vm.define_native("fileExists", 1, file_exists);
+    vm.define_native("readLine", 0, read_line);
+}
+
+/// The natives stay defined when I/O is disabled, so scripts get a clear
+/// runtime error instead of an undefined variable.
+fn check_enabled(vm: &Vm, name: &str) -> Result<(), RuntimeError> {
+    if vm.io_enabled() {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(format!(
+            "'{}' is not available, I/O is disabled.",
+            name
+        )))
+    }
+}
+
+fn read_file(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    check_enabled(vm, "readFile")?;
+    let path = string_arg("readFile", args, 0)?;
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Value::from(contents)),
+        Err(err) => Err(RuntimeError::new(format!(
+            "Could not read file \"{}\": {}.",
+            path, err
+        ))),
+    }
+}
+
+fn write_file(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    check_enabled(vm, "writeFile")?;
+    let path = string_arg("writeFile", args, 0)?;
+    let contents = string_arg("writeFile", args, 1)?;
+    match fs::write(path, contents) {
+        Ok(()) => Ok(Value::Nil),
+        Err(err) => Err(RuntimeError::new(format!(
+            "Could not write file \"{}\": {}.",
+            path, err
+        ))),
+    }
+}
+
+fn append_file(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    check_enabled(vm, "appendFile")?;
+    let path = string_arg("appendFile", args, 0)?;
+    let contents = string_arg("appendFile", args, 1)?;
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(()) => Ok(Value::Nil),
+        Err(err) => Err(RuntimeError::new(format!(
+            "Could not write file \"{}\": {}.",
+            path, err
+        ))),
+    }
+}
+
+fn file_exists(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    check_enabled(vm, "fileExists")?;
+    let path = string_arg("fileExists", args, 0)?;
+    Ok(Value::Bool(Path::new(path).is_file()))
+}
+
+/// The next line of input without its line ending, or `nil` at the end of
+/// the input.
+fn read_line(vm: &mut Vm, _args: &[Value]) -> Result<Value, RuntimeError> {
+    check_enabled(vm, "readLine")?;
+    let mut line = String::new();
+    match vm.stdin().read_line(&mut line) {
+        Ok(0) => Ok(Value::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Value::from(line))
+        }
+        Err(err) => Err(RuntimeError::new(format!("Could not read input: {}.", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+    use crate::vm::LoxError;
+    use std::env;
+    use std::io::Cursor;
+
+    fn io_vm() -> Vm {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+        vm.set_io_enabled(true);
+        vm
+    }
+
+    #[test]
+    fn read_and_write_files() {
+        let path = env::temp_dir().join(format!("lox-io-test-{}.txt", std::process::id()));
+        let mut vm = io_vm();
+        vm.set_global("path", path.to_str().unwrap());
+
+        let source = "
+            writeFile(path, \"one\");
+            appendFile(path, \" two\");
+            fileExists(path) and readFile(path);
+        ";
+        let result = vm.interpret(source);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, Ok(Value::from("one two")));
+        assert_eq!(vm.interpret("fileExists(path);"), Ok(Value::Bool(false)));
+        match vm.interpret("readFile(path);") {
+            Err(LoxError::Runtime(message)) => {
+                assert!(message.starts_with("Could not read file \""))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_lines() {
+        let mut vm = io_vm();
+        vm.set_stdin(Box::new(Cursor::new("first\r\nsecond")));
+
+        assert_eq!(vm.interpret("readLine();"), Ok(Value::from("first")));
+        assert_eq!(vm.interpret("readLine();"), Ok(Value::from("second")));
+        assert_eq!(vm.interpret("readLine();"), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+
+        assert_eq!(
+            vm.interpret("readFile(\"/etc/passwd\");"),
+            Err(LoxError::Runtime(
+                "'readFile' is not available, I/O is disabled.".to_string()
+            ))
+        );
+        assert!(vm.interpret("readLine();").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use super::{index_arg, list_arg};
+use crate::value::Value;
+use crate::vm::{RuntimeError, Vm};
+
+pub fn define(vm: &mut Vm) {
+    vm.define_native("push", 2, push);
+    vm.define_native("pop", 1, pop);
+    vm.define_native("insert", 3, insert);
+    vm.define_native("remove", 2, remove);
+    vm.define_native("slice", 3, slice);
+}
+
+/// `push(list, value)` appends `value` to `list`.
+fn push(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("push", args, 0)?;
+    list.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+/// `pop(list)` removes and returns the last element.
+fn pop(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("pop", args, 0)?;
+    let last = list.borrow_mut().pop();
+    last.ok_or_else(|| RuntimeError::new("Cannot pop from an empty list."))
+}
+
+/// `insert(list, index, value)` puts `value` at `index`, shifting the
+/// elements after it. `index` may be the length of the list.
+fn insert(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("insert", args, 0)?;
+    let idx = index_arg("insert", args, 1)?;
+
+    let mut elements = list.borrow_mut();
+    if idx > elements.len() {
+        return Err(out_of_range(idx, elements.len()));
+    }
+    elements.insert(idx, args[2].clone());
+    Ok(Value::Nil)
+}
+
+/// `remove(list, index)` removes and returns the element at `index`.
+fn remove(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("remove", args, 0)?;
+    let idx = index_arg("remove", args, 1)?;
+
+    let mut elements = list.borrow_mut();
+    if idx >= elements.len() {
+        return Err(out_of_range(idx, elements.len()));
+    }
+    Ok(elements.remove(idx))
+}
+
+/// `slice(list, start, end)` is a new list with the elements from `start`
+/// up to, but not including, `end`.
+fn slice(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("slice", args, 0)?;
+    let start = index_arg("slice", args, 1)?;
+    let end = index_arg("slice", args, 2)?;
+
+    let elements = list.borrow();
+    if start > end || end > elements.len() {
+        return Err(RuntimeError::new(format!(
+            "Slice {}..{} out of range for length {}.",
+            start,
+            end,
+            elements.len()
+        )));
+    }
+    Ok(Value::from(elements[start..end].to_vec()))
+}
+
+fn out_of_range(idx: usize, len: usize) -> RuntimeError {
+    RuntimeError::new(format!(
+        "List index {} out of range for length {}.",
+        idx, len
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::natives::tests::{eval, show};
+    use crate::vm::LoxError;
+
+    #[test]
+    fn grow_and_shrink() {
+        assert_eq!(show("var a = [1]; push(a, 2); push(a, 3); a;"), "[1, 2, 3]");
+        assert_eq!(show("var a = [1, 2]; pop(a) + len(a);"), "3");
+        assert_eq!(
+            show("var a = [1, 3]; insert(a, 1, 2); insert(a, 3, 4); a;"),
+            "[1, 2, 3, 4]"
+        );
+        assert_eq!(show("var a = [1, 2, 3]; remove(a, 0) + len(a);"), "3");
+        assert_eq!(show("var a = [1, 2, 3, 4]; slice(a, 1, 3);"), "[2, 3]");
+        assert_eq!(show("slice([1, 2], 2, 2);"), "[]");
+    }
+
+    #[test]
+    fn list_errors() {
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+
+        assert_eq!(eval("pop([]);"), error("Cannot pop from an empty list."));
+        assert_eq!(
+            eval("remove([1], 1);"),
+            error("List index 1 out of range for length 1.")
+        );
+        assert_eq!(
+            eval("insert([1], 2, 0);"),
+            error("List index 2 out of range for length 1.")
+        );
+        assert_eq!(
+            eval("slice([1, 2], 2, 1);"),
+            error("Slice 2..1 out of range for length 2.")
+        );
+        assert_eq!(
+            eval("push(\"a\", 1);"),
+            error("Argument 1 of 'push' must be a list.")
+        );
+    }
+}
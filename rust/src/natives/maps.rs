@@ -0,0 +1,84 @@
+use super::map_arg;
+use crate::value::{sorted_entries, Key, Value};
+use crate::vm::{unhashable, RuntimeError, Vm};
+
+pub fn define(vm: &mut Vm) {
+    vm.define_native("has", 2, has);
+    vm.define_native("delete", 2, delete);
+    vm.define_native("keys", 1, keys);
+    vm.define_native("values", 1, values);
+}
+
+/// `has(map, key)` tells whether `map` has an entry for `key`.
+fn has(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = map_arg("has", args, 0)?;
+    let key = key_arg(args, 1)?;
+    Ok(Value::Bool(map.borrow().contains(&key)))
+}
+
+/// `delete(map, key)` removes the entry for `key` and tells whether there
+/// was one.
+fn delete(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = map_arg("delete", args, 0)?;
+    let key = key_arg(args, 1)?;
+    Ok(Value::Bool(map.borrow_mut().delete(&key)))
+}
+
+/// `keys(map)` is the list of the keys of `map`, in key order.
+fn keys(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = map_arg("keys", args, 0)?;
+    let entries = sorted_entries(&map.borrow());
+    Ok(Value::from(
+        entries
+            .iter()
+            .map(|(key, _)| key.to_value())
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// `values(map)` is the list of the values of `map`, in the order of their
+/// keys.
+fn values(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = map_arg("values", args, 0)?;
+    let entries = sorted_entries(&map.borrow());
+    Ok(Value::from(
+        entries
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn key_arg(args: &[Value], idx: usize) -> Result<Key, RuntimeError> {
+    Key::from_value(&args[idx]).ok_or_else(|| RuntimeError::new(unhashable(&args[idx])))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::natives::tests::{eval, show};
+    use crate::vm::LoxError;
+
+    #[test]
+    fn inspect_maps() {
+        let map = "var m = {\"b\": 2, \"a\": 1, 0: nil};";
+        let with_map = |source: &str| show(&format!("{} {}", map, source));
+
+        assert_eq!(with_map("keys(m);"), "[0, a, b]");
+        assert_eq!(with_map("values(m);"), "[nil, 1, 2]");
+        assert_eq!(with_map("has(m, \"a\") and !has(m, \"c\");"), "true");
+        assert_eq!(with_map("len(m);"), "3");
+        assert_eq!(with_map("delete(m, \"a\") and !delete(m, \"a\");"), "true");
+        assert_eq!(with_map("delete(m, 0); m;"), "{a: 1, b: 2}");
+    }
+
+    #[test]
+    fn map_errors() {
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+
+        assert_eq!(eval("has({}, []);"), error("A list cannot be a map key."));
+        assert_eq!(
+            eval("keys([]);"),
+            error("Argument 1 of 'keys' must be a map.")
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use super::number_arg;
+use crate::value::Value;
+use crate::vm::{RuntimeError, Vm};
+
+pub fn define(vm: &mut Vm) {
+    vm.define_native("sqrt", 1, |_, args| unary("sqrt", args, f64::sqrt));
+    vm.define_native("floor", 1, |_, args| unary("floor", args, f64::floor));
+    vm.define_native("ceil", 1, |_, args| unary("ceil", args, f64::ceil));
+    vm.define_native("round", 1, |_, args| unary("round", args, f64::round));
+    vm.define_native("abs", 1, |_, args| unary("abs", args, f64::abs));
+    vm.define_native("sin", 1, |_, args| unary("sin", args, f64::sin));
+    vm.define_native("cos", 1, |_, args| unary("cos", args, f64::cos));
+    vm.define_native("tan", 1, |_, args| unary("tan", args, f64::tan));
+    vm.define_native("log", 1, |_, args| unary("log", args, f64::ln));
+    vm.define_native("exp", 1, |_, args| unary("exp", args, f64::exp));
+    vm.define_native("pow", 2, |_, args| binary("pow", args, f64::powf));
+    vm.define_native("min", 2, |_, args| binary("min", args, f64::min));
+    vm.define_native("max", 2, |_, args| binary("max", args, f64::max));
+    vm.define_native("random", 0, random);
+    vm.define_native("seedRandom", 1, seed_random);
+}
+
+fn unary(name: &str, args: &[Value], op: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(op(number_arg(name, args, 0)?)))
+}
+
+fn binary(name: &str, args: &[Value], op: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+    let a = number_arg(name, args, 0)?;
+    let b = number_arg(name, args, 1)?;
+    Ok(Value::Number(op(a, b)))
+}
+
+/// A number in `[0, 1)`.
+fn random(vm: &mut Vm, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(vm.random().next_f64()))
+}
+
+/// `seedRandom(seed)` restarts the sequence returned by `random()`.
+fn seed_random(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let seed = number_arg("seedRandom", args, 0)?;
+    vm.random().seed(seed.to_bits());
+    Ok(Value::Nil)
+}
+
+/// SplitMix64, a small generator whose sequence depends only on the seed,
+/// so a seeded script gives the same numbers on every platform.
+#[derive(Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa of a double exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::natives::tests::eval;
+    use crate::vm::LoxError;
+
+    #[test]
+    fn math_functions() {
+        assert_eq!(eval("sqrt(16);"), Ok(Value::Number(4.0)));
+        assert_eq!(eval("pow(2, 10);"), Ok(Value::Number(1024.0)));
+        assert_eq!(
+            eval("floor(1.5) + ceil(1.5) + round(2.5);"),
+            Ok(Value::Number(6.0))
+        );
+        assert_eq!(
+            eval("abs(-3) + min(1, 2) + max(1, 2);"),
+            Ok(Value::Number(6.0))
+        );
+        assert_eq!(eval("sin(0) + cos(0) + tan(0);"), Ok(Value::Number(1.0)));
+        assert_eq!(eval("log(exp(2));"), Ok(Value::Number(2.0)));
+        assert_eq!(
+            eval("sqrt(\"4\");"),
+            Err(LoxError::Runtime(
+                "Argument 1 of 'sqrt' must be a number.".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn seeded_random_repeats() {
+        let source = "seedRandom(42); var a = random(); seedRandom(42); a == random();";
+        assert_eq!(eval(source), Ok(Value::Bool(true)));
+
+        let mut vm = Vm::new();
+        vm.set_random_seed(7);
+        let first = vm.interpret("random();").unwrap();
+        vm.set_random_seed(7);
+        assert_eq!(vm.interpret("random();").unwrap(), first);
+    }
+
+    #[test]
+    fn random_range() {
+        let mut random = Random::new(1);
+        for _ in 0..1000 {
+            let value = random.next_f64();
+            assert!((0.0..1.0).contains(&value));
+        }
+        assert_ne!(random.next_u64(), random.next_u64());
+    }
+}
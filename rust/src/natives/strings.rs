@@ -0,0 +1,226 @@
+use super::{index_arg, list_arg, string_arg};
+use crate::value::{print_value, Value};
+use crate::vm::{RuntimeError, Vm};
+use std::convert::TryFrom;
+
+pub fn define(vm: &mut Vm) {
+    vm.define_native("substr", 3, substr);
+    vm.define_native("indexOf", 2, index_of);
+    vm.define_native("split", 2, split);
+    vm.define_native("join", 2, join);
+    vm.define_native("upper", 1, upper);
+    vm.define_native("lower", 1, lower);
+    vm.define_native("trim", 1, trim);
+    vm.define_native("replace", 3, replace);
+    vm.define_native("startsWith", 2, starts_with);
+    vm.define_native("chr", 1, chr);
+    vm.define_native("ord", 1, ord);
+    vm.define_native("str", 1, str);
+}
+
+// Lengths and positions count characters, not bytes.
+
+/// `substr(string, start, length)`
+fn substr(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("substr", args, 0)?;
+    let start = index_arg("substr", args, 1)?;
+    let length = index_arg("substr", args, 2)?;
+
+    if start + length > string.chars().count() {
+        return Err(RuntimeError::new("Substring out of range."));
+    }
+    let result: String = string.chars().skip(start).take(length).collect();
+    Ok(Value::from(result))
+}
+
+/// `indexOf(string, needle)` is the position of the first `needle` in
+/// `string`, or -1.
+fn index_of(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("indexOf", args, 0)?;
+    let needle = string_arg("indexOf", args, 1)?;
+
+    let index = match string.find(needle) {
+        Some(byte) => string[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::Number(index))
+}
+
+/// `split(string, separator)` is the list of the pieces between the
+/// separators.
+fn split(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("split", args, 0)?;
+    let separator = string_arg("split", args, 1)?;
+
+    if separator.is_empty() {
+        return Err(RuntimeError::new("Cannot split on an empty separator."));
+    }
+    let pieces = string.split(separator).map(Value::from).collect::<Vec<_>>();
+    Ok(Value::from(pieces))
+}
+
+/// `join(list, separator)` formats the elements like `str()` does and puts
+/// `separator` between them.
+fn join(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let list = list_arg("join", args, 0)?;
+    let separator = string_arg("join", args, 1)?;
+
+    let pieces: Vec<String> = list.borrow().iter().map(Value::to_string).collect();
+    Ok(Value::from(pieces.join(separator)))
+}
+
+fn upper(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string_arg("upper", args, 0)?.to_uppercase()))
+}
+
+fn lower(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string_arg("lower", args, 0)?.to_lowercase()))
+}
+
+fn trim(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(string_arg("trim", args, 0)?.trim()))
+}
+
+/// `replace(string, from, to)` replaces every `from` in `string`.
+fn replace(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("replace", args, 0)?;
+    let from = string_arg("replace", args, 1)?;
+    let to = string_arg("replace", args, 2)?;
+
+    if from.is_empty() {
+        return Err(RuntimeError::new("Cannot replace an empty string."));
+    }
+    Ok(Value::from(string.replace(from, to)))
+}
+
+fn starts_with(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("startsWith", args, 0)?;
+    let prefix = string_arg("startsWith", args, 1)?;
+    Ok(Value::Bool(string.starts_with(prefix)))
+}
+
+/// `chr(code)` is the one character string for the Unicode code point.
+fn chr(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = index_arg("chr", args, 0)?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(Value::from(c.to_string())),
+        None => Err(RuntimeError::new(format!(
+            "{} is not a valid character code.",
+            code
+        ))),
+    }
+}
+
+/// `ord(char)` is the Unicode code point of a one character string.
+fn ord(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg("ord", args, 0)?;
+    let mut chars = string.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::Number(c as u32 as f64)),
+        _ => Err(RuntimeError::new(
+            "Argument 1 of 'ord' must be a single character.",
+        )),
+    }
+}
+
+/// `str(value)` is `value` formatted the way `print` shows it.
+fn str(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    if let Value::String(_) = &args[0] {
+        return Ok(args[0].clone());
+    }
+
+    let mut out = Vec::new();
+    print_value(&mut out, &args[0]).expect("writing to a Vec cannot fail");
+    Ok(Value::from(String::from_utf8_lossy(&out).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::natives::tests::eval;
+    use crate::value::Value;
+    use crate::vm::LoxError;
+
+    #[test]
+    fn inspect_strings() {
+        let hello = "var hello = \"h\" + chr(233) + \"llo\";";
+        let eval_hello = |source: &str| eval(&format!("{} {}", hello, source));
+
+        assert_eq!(eval_hello("len(hello);"), Ok(Value::Number(5.0)));
+        assert_eq!(eval_hello("substr(hello, 1, 3);"), Ok(Value::from("éll")));
+        assert_eq!(eval_hello("indexOf(hello, \"l\");"), Ok(Value::Number(2.0)));
+        assert_eq!(eval("indexOf(\"hello\", \"x\");"), Ok(Value::Number(-1.0)));
+        assert_eq!(
+            eval("startsWith(\"hello\", \"he\");"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(eval("ord(\"A\");"), Ok(Value::Number(65.0)));
+        assert_eq!(eval("chr(955);"), Ok(Value::from("λ")));
+    }
+
+    #[test]
+    fn split_and_join() {
+        assert_eq!(
+            eval("split(\"a,b,,c\", \",\");").unwrap().to_string(),
+            "[a, b, , c]"
+        );
+        assert_eq!(
+            eval("join(split(\"a b c\", \" \"), \"-\");"),
+            Ok(Value::from("a-b-c"))
+        );
+        assert_eq!(
+            eval("join([1, nil, true], \"\");"),
+            Ok(Value::from("1niltrue"))
+        );
+        assert!(eval("split(\"abc\", \"\");").is_err());
+    }
+
+    #[test]
+    fn transform_strings() {
+        assert_eq!(eval("upper(\"Lox\");"), Ok(Value::from("LOX")));
+        assert_eq!(eval("lower(\"Lox\");"), Ok(Value::from("lox")));
+        assert_eq!(eval("trim(\"  lox  \");"), Ok(Value::from("lox")));
+        assert_eq!(
+            eval("replace(\"a-b-c\", \"-\", \"+\");"),
+            Ok(Value::from("a+b+c"))
+        );
+    }
+
+    #[test]
+    fn convert_to_string() {
+        assert_eq!(eval("str(1.5) + str(2);"), Ok(Value::from("1.52")));
+        assert_eq!(eval("str(nil);"), Ok(Value::from("nil")));
+        assert_eq!(eval("str(1000000 * 1000000);"), Ok(Value::from("1.0E12")));
+        assert_eq!(eval("str(\"lox\");"), Ok(Value::from("lox")));
+    }
+
+    #[test]
+    fn wrong_arguments() {
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+
+        assert_eq!(
+            eval("len(1);"),
+            error("Argument 1 of 'len' must be a string, a list or a map.")
+        );
+        assert_eq!(
+            eval("substr(\"lox\", 1, nil);"),
+            error("Argument 3 of 'substr' must be a number.")
+        );
+        assert_eq!(
+            eval("substr(\"lox\", 1.5, 1);"),
+            error("Argument 2 of 'substr' must be a non-negative integer.")
+        );
+        assert_eq!(
+            eval("substr(\"lox\", 2, 2);"),
+            error("Substring out of range.")
+        );
+        assert_eq!(
+            eval("ord(\"ab\");"),
+            error("Argument 1 of 'ord' must be a single character.")
+        );
+        assert_eq!(
+            eval("chr(55296);"),
+            error("55296 is not a valid character code.")
+        );
+        assert_eq!(eval("upper();"), error("Expected 1 arguments but got 0."));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::chunk::Chunk;
+use crate::value::{List, Value};
+use crate::vm::{RuntimeError, Vm};
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// Name used for the top level code of a script in traces and listings.
+pub const SCRIPT_NAME: &str = "script";
+
+/// An immutable Lox string. Clones share the text. It is one pointer wide,
+/// so a NaN box holds it without allocating.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LoxString(pub(crate) Rc<String>);
+
+impl Deref for LoxString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<str> for LoxString {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for LoxString {
+    fn from(string: &str) -> LoxString {
+        LoxString(Rc::new(string.to_string()))
+    }
+}
+
+impl From<String> for LoxString {
+    fn from(string: String) -> LoxString {
+        LoxString(Rc::new(string))
+    }
+}
+
+impl fmt::Display for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self.0, f)
+    }
+}
+
+/// A function compiled from Lox source. The top level code of a script is
+/// compiled into a function without a name.
+#[derive(Debug)]
+pub struct Function {
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub name: Option<String>,
+}
+
+impl Function {
+    pub fn new(name: Option<&str>) -> Function {
+        Function {
+            arity: 0,
+            chunk: Chunk::new(),
+            name: name.map(str::to_string),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(SCRIPT_NAME)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// Signature of the Rust functions exposed to Lox with [`Vm::define_native`].
+/// The arguments have already been checked against the declared arity.
+pub type NativeFn = fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust.
+#[derive(Debug)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+/// The state of a `for (var x in ...)` loop. `range()` returns one too.
+#[derive(Debug)]
+pub enum Iter {
+    /// Sees elements pushed while the loop runs.
+    List {
+        list: Rc<RefCell<List>>,
+        index: usize,
+    },
+    /// The keys of a map, taken in key order when the loop starts.
+    Keys {
+        keys: Vec<Value>,
+        index: usize,
+    },
+    /// The characters of a string.
+    Chars {
+        string: LoxString,
+        offset: usize,
+    },
+    Range {
+        next: f64,
+        end: f64,
+        step: f64,
+    },
+}
+
+impl Iterator for Iter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Iter::List { list, index } => {
+                let element = list.borrow().get(*index).cloned()?;
+                *index += 1;
+                Some(element)
+            }
+            Iter::Keys { keys, index } => {
+                let key = keys.get(*index).cloned()?;
+                *index += 1;
+                Some(key)
+            }
+            Iter::Chars { string, offset } => {
+                let c = string[*offset..].chars().next()?;
+                *offset += c.len_utf8();
+                Some(Value::from(c.to_string()))
+            }
+            Iter::Range { next, end, step } => {
+                let done = if *step > 0.0 {
+                    *next >= *end
+                } else {
+                    *next <= *end
+                };
+                if done {
+                    return None;
+                }
+                let value = *next;
+                *next += *step;
+                Some(Value::Number(value))
+            }
+        }
+    }
+}
@@ -0,0 +1,758 @@
+//! A peephole optimizer for compiled functions. It folds arithmetic and
+//! comparisons on constants, drops instructions that cancel out or can
+//! never run, and sends jumps that land on other jumps straight to where
+//! those go. Last, it fuses common sequences into superinstructions.
+//!
+//! The chunk is decoded into a list of instructions whose jumps hold the
+//! index of the instruction they go to, so instructions can be removed
+//! without fixing up offsets. Encoding it again recomputes the offsets and
+//! the line of every byte.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::line_number::LineNumber;
+use crate::object::Function;
+use crate::value::Value;
+use std::rc::Rc;
+
+/// Optimizes the code of `function` and of every function declared in it.
+pub fn optimize(function: &mut Function) {
+    for idx in 0..function.chunk.constants.len() {
+        // Freshly compiled functions are not shared yet, once taken out.
+        let mut constant = function.chunk.constants.replace(idx, Value::Nil);
+        if let Value::Function(inner) = &mut constant {
+            if let Some(inner) = Rc::get_mut(inner) {
+                optimize(inner);
+            }
+        }
+        function.chunk.constants.replace(idx, constant);
+    }
+
+    // The optimized code is never longer, so its jumps still fit in two
+    // bytes when the original chunk does.
+    if function.chunk.code.len() > u16::MAX as usize {
+        return;
+    }
+    let mut code = Code::decode(&function.chunk);
+    while code.optimize(&mut function.chunk) {}
+    code.fuse(&function.chunk);
+    // A fused jump skips the `OP_POP` after a loop, which leaves it dead.
+    code.remove_unreachable();
+    code.encode(&mut function.chunk);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Operand {
+    None,
+    Byte,
+    Jump,
+}
+
+fn operand(op: OpCode) -> Operand {
+    match op {
+        OpCode::OpConstant
+        | OpCode::OpGetLocal
+        | OpCode::OpSetLocal
+        | OpCode::OpRethrow
+        | OpCode::OpGetGlobal
+        | OpCode::OpDefineGlobal
+        | OpCode::OpSetGlobal
+        | OpCode::OpCall
+        | OpCode::OpBuildList
+        | OpCode::OpBuildMap
+        | OpCode::OpBuildString
+        | OpCode::OpAddConstant
+        | OpCode::OpIncrementLocal => Operand::Byte,
+        OpCode::OpJump
+        | OpCode::OpJumpIfFalse
+        | OpCode::OpLoop
+        | OpCode::OpIterNext
+        | OpCode::OpTry
+        | OpCode::OpLessJumpIfFalse => Operand::Jump,
+        _ => Operand::None,
+    }
+}
+
+fn size(op: OpCode) -> usize {
+    match operand(op) {
+        Operand::None => 1,
+        Operand::Byte => 2,
+        Operand::Jump => 3,
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Instruction {
+    op: OpCode,
+    /// The operand of instructions that take a byte.
+    byte: u8,
+    /// The index of the instruction a jump goes to.
+    target: usize,
+    line: usize,
+    removed: bool,
+}
+
+impl Instruction {
+    fn new(op: OpCode, line: usize) -> Instruction {
+        Instruction {
+            op,
+            byte: 0,
+            target: 0,
+            line,
+            removed: false,
+        }
+    }
+
+    fn is_jump(&self) -> bool {
+        operand(self.op) == Operand::Jump
+    }
+
+    /// Whether the instruction after this one can only be reached by
+    /// jumping to it.
+    fn ends_flow(&self) -> bool {
+        matches!(
+            self.op,
+            OpCode::OpJump
+                | OpCode::OpLoop
+                | OpCode::OpReturn
+                | OpCode::OpThrow
+                | OpCode::OpRethrow
+        )
+    }
+}
+
+struct Code {
+    instructions: Vec<Instruction>,
+}
+
+impl Code {
+    fn decode(chunk: &Chunk) -> Code {
+        let mut instructions = Vec::new();
+        // The index of the instruction starting at each offset.
+        let mut index_at = vec![usize::MAX; chunk.code.len() + 1];
+        let byte = |offset: usize| match chunk.code.get(offset) {
+            Some(OpCode::Reg(byte)) => *byte,
+            _ => panic!("Invalid item"),
+        };
+
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let op = chunk.code[offset];
+            let mut instruction = Instruction::new(op, chunk.lines.get_line(offset));
+            match operand(op) {
+                Operand::None => {}
+                Operand::Byte => instruction.byte = byte(offset + 1),
+                Operand::Jump => {
+                    let jump = (byte(offset + 1) as usize) << 8 | byte(offset + 2) as usize;
+                    // The target offset for now, an index once all are known.
+                    instruction.target = match op {
+                        OpCode::OpLoop => offset + 3 - jump,
+                        _ => offset + 3 + jump,
+                    };
+                }
+            }
+            index_at[offset] = instructions.len();
+            instructions.push(instruction);
+            offset += size(op);
+        }
+        index_at[chunk.code.len()] = instructions.len();
+
+        for instruction in &mut instructions {
+            if instruction.is_jump() {
+                instruction.target = index_at[instruction.target];
+            }
+        }
+        Code { instructions }
+    }
+
+    fn encode(&self, chunk: &mut Chunk) {
+        // The offset of every instruction, where removed ones start the same
+        // place as the next one that is kept.
+        let mut offsets = Vec::with_capacity(self.instructions.len() + 1);
+        let mut offset = 0;
+        for instruction in &self.instructions {
+            offsets.push(offset);
+            if !instruction.removed {
+                offset += size(instruction.op);
+            }
+        }
+        offsets.push(offset);
+
+        chunk.code.clear();
+        chunk.lines = LineNumber::new();
+        for (idx, instruction) in self.instructions.iter().enumerate() {
+            if instruction.removed {
+                continue;
+            }
+            let line = instruction.line;
+            chunk.add_chunk(instruction.op, line);
+            match operand(instruction.op) {
+                Operand::None => {}
+                Operand::Byte => chunk.add_chunk(OpCode::Reg(instruction.byte), line),
+                Operand::Jump => {
+                    let after = offsets[idx] + 3;
+                    let target = offsets[instruction.target];
+                    let jump = match instruction.op {
+                        OpCode::OpLoop => after - target,
+                        _ => target - after,
+                    };
+                    chunk.add_chunk(OpCode::Reg((jump >> 8) as u8), line);
+                    chunk.add_chunk(OpCode::Reg(jump as u8), line);
+                }
+            }
+        }
+    }
+
+    /// Runs every optimization once and tells whether any changed the code.
+    fn optimize(&mut self, chunk: &mut Chunk) -> bool {
+        self.fold_constants(chunk)
+            | self.remove_double_nots()
+            | self.remove_dead_pops()
+            | self.thread_jumps()
+            | self.remove_unreachable()
+    }
+
+    /// The indices of the instructions that have not been removed.
+    fn live(&self) -> Vec<usize> {
+        (0..self.instructions.len())
+            .filter(|&idx| !self.instructions[idx].removed)
+            .collect()
+    }
+
+    /// The first instruction from `idx` on that has not been removed, which
+    /// is where a jump to `idx` now lands.
+    fn resolve(&self, idx: usize) -> usize {
+        (idx..self.instructions.len())
+            .find(|&idx| !self.instructions[idx].removed)
+            .unwrap_or(self.instructions.len())
+    }
+
+    /// Marks the instructions that jumps land on. Patterns spanning one of
+    /// them can't be rewritten, since the jump would skip part of them.
+    fn targets(&self) -> Vec<bool> {
+        let mut targets = vec![false; self.instructions.len() + 1];
+        for instruction in &self.instructions {
+            if instruction.is_jump() && !instruction.removed {
+                targets[self.resolve(instruction.target)] = true;
+            }
+        }
+        targets
+    }
+
+    fn remove(&mut self, idx: usize) {
+        self.instructions[idx].removed = true;
+    }
+
+    /// Replaces `OP_CONSTANT 1; OP_CONSTANT 2; OP_ADD` with the result,
+    /// and does the same for negation and `!` of a literal. Division and
+    /// modulo by zero are left for the VM to report.
+    fn fold_constants(&mut self, chunk: &mut Chunk) -> bool {
+        let targets = self.targets();
+        let live = self.live();
+        let mut changed = false;
+
+        let mut pos = 0;
+        while pos < live.len() {
+            let a = live[pos];
+            let b = live.get(pos + 1).copied();
+            let c = live.get(pos + 2).copied();
+
+            if let (Some(b), Some(c)) = (b, c) {
+                if !targets[b] && !targets[c] {
+                    let folded = match (self.literal(a, chunk), self.literal(b, chunk)) {
+                        (Some(x), Some(y)) => fold_binary(self.instructions[c].op, x, y),
+                        _ => None,
+                    };
+                    let line = self.instructions[c].line;
+                    if let Some(load) = folded.and_then(|value| load(value, line, chunk)) {
+                        self.instructions[a] = load;
+                        self.remove(b);
+                        self.remove(c);
+                        changed = true;
+                        pos += 3;
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(b) = b {
+                if !targets[b] {
+                    let folded = self
+                        .literal(a, chunk)
+                        .and_then(|x| fold_unary(self.instructions[b].op, x));
+                    let line = self.instructions[b].line;
+                    if let Some(load) = folded.and_then(|value| load(value, line, chunk)) {
+                        self.instructions[a] = load;
+                        self.remove(b);
+                        changed = true;
+                        pos += 2;
+                        continue;
+                    }
+                }
+            }
+            pos += 1;
+        }
+        changed
+    }
+
+    /// The value a literal instruction pushes.
+    fn literal(&self, idx: usize, chunk: &Chunk) -> Option<Value> {
+        let instruction = &self.instructions[idx];
+        match instruction.op {
+            OpCode::OpNil => Some(Value::Nil),
+            OpCode::OpTrue => Some(Value::Bool(true)),
+            OpCode::OpFalse => Some(Value::Bool(false)),
+            OpCode::OpConstant => match chunk.constants.get(instruction.byte as usize) {
+                value @ Value::Number(_) | value @ Value::String(_) => Some(value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// `a <= b` compiles to `OP_GREATER; OP_NOT`, so `!(a <= b)` gives two
+    /// `OP_NOT`s in a row. After an instruction that already pushes a
+    /// boolean they do nothing.
+    fn remove_double_nots(&mut self) -> bool {
+        let targets = self.targets();
+        let live = self.live();
+        let mut changed = false;
+
+        for window in live.windows(3) {
+            let (value, first, second) = (window[0], window[1], window[2]);
+            if self.instructions[value].removed || targets[first] || targets[second] {
+                continue;
+            }
+            let pushes_bool = matches!(
+                self.instructions[value].op,
+                OpCode::OpEqual
+                    | OpCode::OpGreater
+                    | OpCode::OpLess
+                    | OpCode::OpNot
+                    | OpCode::OpTrue
+                    | OpCode::OpFalse
+            );
+            if pushes_bool
+                && self.instructions[first].op == OpCode::OpNot
+                && self.instructions[second].op == OpCode::OpNot
+            {
+                self.remove(first);
+                self.remove(second);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Drops a value pushed only to be popped again, like the `1;`
+    /// expression statement.
+    fn remove_dead_pops(&mut self) -> bool {
+        let targets = self.targets();
+        let live = self.live();
+        let mut changed = false;
+
+        for window in live.windows(2) {
+            let (push, pop) = (window[0], window[1]);
+            if self.instructions[push].removed || targets[pop] {
+                continue;
+            }
+            let pure = matches!(
+                self.instructions[push].op,
+                OpCode::OpConstant
+                    | OpCode::OpNil
+                    | OpCode::OpTrue
+                    | OpCode::OpFalse
+                    | OpCode::OpGetLocal
+                    | OpCode::OpGetLocal0
+                    | OpCode::OpGetLocal1
+                    | OpCode::OpGetLocal2
+                    | OpCode::OpGetLocal3
+            );
+            if pure && self.instructions[pop].op == OpCode::OpPop {
+                self.remove(push);
+                self.remove(pop);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Points a jump that lands on an unconditional jump at that jump's
+    /// target. A conditional jump that lands on another one leaves the
+    /// same falsey value on the stack, so it goes on to that one's target.
+    /// Jumps to the instruction right after them are removed.
+    fn thread_jumps(&mut self) -> bool {
+        let mut changed = false;
+
+        for idx in self.live() {
+            let instruction = self.instructions[idx];
+            let conditional = instruction.op == OpCode::OpJumpIfFalse;
+            if !conditional && !matches!(instruction.op, OpCode::OpJump | OpCode::OpLoop) {
+                continue;
+            }
+
+            let mut target = self.resolve(instruction.target);
+            let mut steps = 0;
+            while let Some(next) = self.instructions.get(target) {
+                let follows = match next.op {
+                    OpCode::OpJump | OpCode::OpLoop => true,
+                    OpCode::OpJumpIfFalse => conditional,
+                    _ => false,
+                };
+                let next_target = self.resolve(next.target);
+                // There is no backward conditional jump.
+                if !follows || (conditional && next_target <= idx) {
+                    break;
+                }
+                target = next_target;
+                steps += 1;
+                if steps > self.instructions.len() {
+                    // Jumps that go round in a circle.
+                    target = self.resolve(instruction.target);
+                    break;
+                }
+            }
+
+            if target == self.resolve(idx + 1) {
+                self.remove(idx);
+                changed = true;
+                continue;
+            }
+
+            let op = match instruction.op {
+                OpCode::OpJumpIfFalse => OpCode::OpJumpIfFalse,
+                _ if target <= idx => OpCode::OpLoop,
+                _ => OpCode::OpJump,
+            };
+            if target != self.resolve(instruction.target) || op != instruction.op {
+                self.instructions[idx].op = op;
+                self.instructions[idx].target = target;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Removes the instructions after a jump, `return` or `throw` that no
+    /// jump lands on.
+    fn remove_unreachable(&mut self) -> bool {
+        let targets = self.targets();
+        let mut reachable = true;
+        let mut changed = false;
+
+        for idx in self.live() {
+            if targets[idx] {
+                reachable = true;
+            }
+            if !reachable {
+                self.remove(idx);
+                changed = true;
+            } else if self.instructions[idx].ends_flow() {
+                reachable = false;
+            }
+        }
+        changed
+    }
+
+    /// Replaces common sequences with superinstructions that do the same
+    /// work in one dispatch:
+    ///
+    /// - `OP_GET_LOCAL s; OP_CONSTANT 1; OP_ADD; OP_SET_LOCAL s` with
+    ///   `OP_INCREMENT_LOCAL s`, which leaves the new value on the stack.
+    /// - `OP_LESS; OP_JUMP_IF_FALSE; OP_POP` with `OP_LESS_JUMP_IF_FALSE`
+    ///   when the jump lands on an `OP_POP` too. The fused instruction pops
+    ///   the operands and jumps past that `OP_POP`.
+    /// - `OP_CONSTANT k; OP_ADD` with `OP_ADD_CONSTANT k`.
+    /// - `OP_GET_LOCAL` of slots 0 to 3 with `OP_GET_LOCAL_0` to `_3`.
+    ///
+    /// Most of the other optimizations don't know the fused instructions,
+    /// so this runs once they are done.
+    fn fuse(&mut self, chunk: &Chunk) {
+        let targets = self.targets();
+        let live = self.live();
+
+        let mut pos = 0;
+        while pos < live.len() {
+            let first = live[pos];
+            let rest = &live[pos + 1..];
+            let instruction = self.instructions[first];
+            let op_at = |n: usize| rest.get(n).map(|&idx| self.instructions[idx].op);
+            let inside = |n: usize| rest[..n].iter().any(|&idx| targets[idx]);
+
+            let fused = match instruction.op {
+                OpCode::OpGetLocal
+                    if op_at(0) == Some(OpCode::OpConstant)
+                        && op_at(1) == Some(OpCode::OpAdd)
+                        && op_at(2) == Some(OpCode::OpSetLocal)
+                        && self.instructions[rest[2]].byte == instruction.byte
+                        && chunk
+                            .constants
+                            .get(self.instructions[rest[0]].byte as usize)
+                            == Value::Number(1.0)
+                        && !inside(3) =>
+                {
+                    Some((OpCode::OpIncrementLocal, instruction.target, 3))
+                }
+                OpCode::OpLess
+                    if op_at(0) == Some(OpCode::OpJumpIfFalse)
+                        && op_at(1) == Some(OpCode::OpPop)
+                        && !inside(2) =>
+                {
+                    let target = self.resolve(self.instructions[rest[0]].target);
+                    match self.instructions.get(target) {
+                        Some(pop) if pop.op == OpCode::OpPop => {
+                            Some((OpCode::OpLessJumpIfFalse, target + 1, 2))
+                        }
+                        _ => None,
+                    }
+                }
+                OpCode::OpConstant if op_at(0) == Some(OpCode::OpAdd) && !inside(1) => {
+                    Some((OpCode::OpAddConstant, instruction.target, 1))
+                }
+                OpCode::OpGetLocal => {
+                    let op = match instruction.byte {
+                        0 => Some(OpCode::OpGetLocal0),
+                        1 => Some(OpCode::OpGetLocal1),
+                        2 => Some(OpCode::OpGetLocal2),
+                        3 => Some(OpCode::OpGetLocal3),
+                        _ => None,
+                    };
+                    op.map(|op| (op, instruction.target, 0))
+                }
+                _ => None,
+            };
+
+            match fused {
+                Some((op, target, absorbed)) => {
+                    for &idx in &rest[..absorbed] {
+                        self.remove(idx);
+                    }
+                    self.instructions[first].op = op;
+                    self.instructions[first].target = target;
+                    pos += 1 + absorbed;
+                }
+                None => pos += 1,
+            }
+        }
+    }
+}
+
+fn fold_binary(op: OpCode, a: Value, b: Value) -> Option<Value> {
+    if op == OpCode::OpEqual {
+        return Some(Value::Bool(a == b));
+    }
+    let (a, b) = match (a, b) {
+        (Value::Number(a), Value::Number(b)) => (a, b),
+        _ => return None,
+    };
+    let value = match op {
+        OpCode::OpAdd => Value::Number(a + b),
+        OpCode::OpSubtract => Value::Number(a - b),
+        OpCode::OpMultiply => Value::Number(a * b),
+        OpCode::OpDivide if b != 0.0 => Value::Number(a / b),
+        OpCode::OpModulo if b != 0.0 => Value::Number(a % b),
+        OpCode::OpGreater => Value::Bool(a > b),
+        OpCode::OpLess => Value::Bool(a < b),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn fold_unary(op: OpCode, value: Value) -> Option<Value> {
+    match (op, value) {
+        (OpCode::OpNot, value) => Some(Value::Bool(value.is_falsey())),
+        (OpCode::OpNegate, Value::Number(n)) => Some(Value::Number(-n)),
+        _ => None,
+    }
+}
+
+/// The instruction that pushes `value`, reusing an equal number constant
+/// of the chunk when there is one. `None` when the chunk has no room for
+/// another constant.
+fn load(value: Value, line: usize, chunk: &mut Chunk) -> Option<Instruction> {
+    let op = match value {
+        Value::Nil => OpCode::OpNil,
+        Value::Bool(true) => OpCode::OpTrue,
+        Value::Bool(false) => OpCode::OpFalse,
+        Value::Number(n) => {
+            let existing = (0..chunk.constants.len()).find(|&idx| {
+                matches!(chunk.constants.get(idx), Value::Number(m) if m.to_bits() == n.to_bits())
+            });
+            let constant = match existing {
+                Some(idx) => idx,
+                None if chunk.constants.len() <= u8::MAX as usize => chunk.add_constant(value),
+                None => return None,
+            };
+            let mut instruction = Instruction::new(OpCode::OpConstant, line);
+            instruction.byte = constant as u8;
+            return Some(instruction);
+        }
+        _ => return None,
+    };
+    Some(Instruction::new(op, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::debug::disassemble_function;
+
+    fn optimized(source: &str) -> Function {
+        let mut function = compile(source).unwrap();
+        optimize(&mut function);
+        function
+    }
+
+    fn listing(source: &str) -> String {
+        let mut out = Vec::new();
+        disassemble_function(&mut out, &optimized(source)).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn fold_constants() {
+        // The folded 7 reuses the constant of the second line.
+        let function = optimized("print -(1 + 2 * 3) < 2 == true;\nprint 7;");
+
+        assert_eq!(
+            function.chunk.code,
+            vec![
+                OpCode::OpTrue,
+                OpCode::OpPrint,
+                OpCode::OpConstant,
+                OpCode::Reg(4),
+                OpCode::OpPrint,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+        assert_eq!(function.chunk.lines.get_line(1), 1);
+        assert_eq!(function.chunk.lines.get_line(2), 2);
+
+        let chunk = optimized("print 10 % 4 - 0.5;").chunk;
+        assert_eq!(chunk.code[1], OpCode::Reg(4));
+        assert_eq!(chunk.constants.get(4), Value::Number(1.5));
+    }
+
+    #[test]
+    fn keep_errors_for_the_vm() {
+        for source in &[
+            "print 1 / 0;",
+            "print 1 % 0;",
+            "print -\"a\";",
+            "print \"a\" < 1;",
+        ] {
+            let code = compile(source).unwrap().chunk.code;
+            assert_eq!(optimized(source).chunk.code, code, "{}", source);
+        }
+    }
+
+    #[test]
+    fn remove_double_nots_and_dead_pops() {
+        assert_eq!(
+            optimized("print !(a <= b);").chunk.code,
+            vec![
+                OpCode::OpGetGlobal,
+                OpCode::Reg(0),
+                OpCode::OpGetGlobal,
+                OpCode::Reg(1),
+                OpCode::OpGreater,
+                OpCode::OpPrint,
+                OpCode::OpNil,
+                OpCode::OpReturn,
+            ]
+        );
+        // `!!a` turns `a` into a boolean, so the nots stay.
+        assert_eq!(
+            optimized("print !!a;").chunk.code[2..4],
+            [OpCode::OpNot, OpCode::OpNot]
+        );
+        assert_eq!(
+            optimized("{ var a = 1; a; 2; }").chunk.code,
+            vec![OpCode::OpNil, OpCode::OpReturn]
+        );
+    }
+
+    #[test]
+    fn thread_jumps() {
+        let listing = listing("if (a) {\n  if (b) print 1; else print 2;\n} else print 3;");
+
+        assert_eq!(
+            listing,
+            "== script ==\n\
+             0000   1 OP_GET_GLOBAL       0 a\n\
+             0002   | OP_JUMP_IF_FALSE    2 -> 25\n\
+             0005   | OP_POP\n\
+             0006   2 OP_GET_GLOBAL       1 b\n\
+             0008   | OP_JUMP_IF_FALSE    8 -> 18\n\
+             0011   | OP_POP\n\
+             0012   | OP_CONSTANT         2 1\n\
+             0014   | OP_PRINT\n\
+             0015   | OP_JUMP            15 -> 29\n\
+             0018   | OP_POP\n\
+             0019   | OP_CONSTANT         3 2\n\
+             0021   | OP_PRINT\n\
+             0022   3 OP_JUMP            22 -> 29\n\
+             0025   | OP_POP\n\
+             0026   | OP_CONSTANT         4 3\n\
+             0028   | OP_PRINT\n\
+             0029   | OP_NIL\n\
+             0030   | OP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn remove_unreachable_code() {
+        let listing = listing("fun f(x) {\n  if (x) return 1;\n  return 2;\n}");
+
+        assert!(listing.ends_with(
+            "== f ==\n\
+             0000   2 OP_GET_LOCAL_1\n\
+             0001   | OP_JUMP_IF_FALSE    1 -> 8\n\
+             0004   | OP_POP\n\
+             0005   | OP_CONSTANT         0 1\n\
+             0007   | OP_RETURN\n\
+             0008   | OP_POP\n\
+             0009   3 OP_CONSTANT         1 2\n\
+             0011   | OP_RETURN\n"
+        ));
+    }
+
+    #[test]
+    fn fuse_superinstructions() {
+        let source = "fun count(n) {\n\
+                      var total = 0;\n\
+                      for (var i = 0; i < n; i = i + 1) total = total + i;\n\
+                      return total + 0.5;\n\
+                      }";
+
+        let listing = listing(source);
+        assert_eq!(
+            listing.split("== count ==\n").nth(1),
+            Some(
+                "0000   2 OP_CONSTANT         0 0\n\
+             0002   3 OP_CONSTANT         1 0\n\
+             0004   | OP_GET_LOCAL_3\n\
+             0005   | OP_GET_LOCAL_1\n\
+             0006   | OP_LESS_JUMP_IF_FALSE    6 -> 27\n\
+             0009   | OP_JUMP             9 -> 18\n\
+             0012   | OP_INCREMENT_LOCAL    3\n\
+             0014   | OP_POP\n\
+             0015   | OP_LOOP            15 -> 4\n\
+             0018   | OP_GET_LOCAL_2\n\
+             0019   | OP_GET_LOCAL_3\n\
+             0020   | OP_ADD\n\
+             0021   | OP_SET_LOCAL        2\n\
+             0023   | OP_POP\n\
+             0024   | OP_LOOP            24 -> 12\n\
+             0027   | OP_POP\n\
+             0028   4 OP_GET_LOCAL_2\n\
+             0029   | OP_ADD_CONSTANT     3 0.5\n\
+             0031   | OP_RETURN\n"
+            )
+        );
+        // Only a jump that lands on an `OP_POP` can skip it.
+        let code = optimized("print a < b and c;").chunk.code;
+        assert_eq!(&code[4..6], &[OpCode::OpLess, OpCode::OpJumpIfFalse]);
+    }
+}
@@ -0,0 +1,123 @@
+use lox::{is_complete, Value, Vm};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".lox_history";
+
+const HELP: &str = "\
+:help          Show this message
+:disasm        Disassemble the last compiled chunk
+:globals       List the global variables defined by scripts
+:reset         Throw away the Vm state and start over
+:load <file>   Run a Lox script inside the current session
+:quit          Leave the REPL";
+
+/// `new_vm` builds the Vm for the session and again on every `:reset`.
+pub fn repl(new_vm: &dyn Fn() -> Vm) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Could not start the line editor: {}", err);
+            std::process::exit(74)
+        }
+    };
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file only means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    let mut vm = new_vm();
+    let mut source = String::new();
+
+    loop {
+        let prompt = if source.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if source.is_empty() && line.trim_start().starts_with(':') {
+                    let _ = editor.add_history_entry(line.trim());
+                    if !run_command(&mut vm, new_vm, line.trim()) {
+                        break;
+                    }
+                    continue;
+                }
+
+                source.push_str(&line);
+                source.push('\n');
+                if !is_complete(&source) {
+                    continue;
+                }
+
+                if !source.trim().is_empty() {
+                    let _ = editor.add_history_entry(source.trim_end());
+                    match vm.interpret(&source) {
+                        Ok(Value::Nil) | Err(_) => {}
+                        Ok(value) => println!("{}", value),
+                    }
+                }
+                source.clear();
+            }
+            // Ctrl-C drops the half typed input, like most shells do.
+            Err(ReadlineError::Interrupted) => source.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Could not save history to \"{}\": {}", path.display(), err);
+        }
+    }
+    println!("Bye!");
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Runs a `:command` line. Returns `false` when the REPL should stop.
+fn run_command(vm: &mut Vm, new_vm: &dyn Fn() -> Vm, line: &str) -> bool {
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let command = parts.next().unwrap_or("");
+    let arg = parts.next().map(str::trim).unwrap_or("");
+
+    match command {
+        ":help" => println!("{}", HELP),
+        ":disasm" => {
+            let _ = vm.disassemble(&mut io::stdout());
+        }
+        ":globals" => {
+            // The built-in natives would drown out the user's own globals.
+            for (name, value) in vm.globals() {
+                if !matches!(value, Value::Native(_)) {
+                    println!("{} = {}", name, value);
+                }
+            }
+        }
+        ":reset" => *vm = new_vm(),
+        ":load" if arg.is_empty() => eprintln!("Usage: :load <file>"),
+        ":load" => match fs::read_to_string(arg) {
+            Ok(source) => {
+                let _ = vm.interpret(&source);
+            }
+            Err(err) => eprintln!("Could not read file \"{}\": {}", arg, err),
+        },
+        ":quit" => return false,
+        _ => eprintln!("Unknown command '{}', try :help.", command),
+    }
+    true
+}
@@ -1,10 +1,13 @@
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum TT {
+pub enum TT {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -12,6 +15,8 @@ enum TT {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Question,
 
     // One or two character tokens.
     Bang,
@@ -26,23 +31,30 @@ enum TT {
     // Literals.
     Identifier,
     String,
+    RawString,
+    Interpolation,
     Number,
 
     // Keywords.
     And,
+    Catch,
     Class,
     Else,
     False,
+    Finally,
     For,
     Fun,
     If,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -50,91 +62,134 @@ enum TT {
     Eof,
 }
 
-pub struct Token {
-    typ: TT,
-    data: *const str,
-    line: usize,
+#[derive(Copy, Clone, Debug)]
+pub struct Token<'a> {
+    pub typ: TT,
+    pub data: &'a str,
+    pub line: usize,
 }
 
+/// `start` and `current` are byte offsets into `source`, always on
+/// character boundaries.
+///
+/// `interpolations` holds one entry for every `${` that has not been
+/// closed yet: the number of braces opened inside it, so the `}` that ends
+/// it can be told apart from one that ends a block or map.
+///
+/// `unterminated` is set once the source ends inside a string,
+/// interpolation or block comment, which more input could still close.
 pub struct Scanner<'a> {
     source: &'a str,
     start: usize,
     current: usize,
     line: usize,
+    interpolations: Vec<usize>,
+    unterminated: bool,
 }
 
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
 fn is_alpha(c: char) -> bool {
-    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+    c.is_ascii_alphabetic() || c == '_'
 }
 
-impl Scanner<'_> {
-    pub fn new(source: &str) -> Scanner {
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
-            source: &source,
+            source,
             start: 0,
             current: 0,
             line: 1,
+            interpolations: Vec::new(),
+            unterminated: false,
         }
     }
 
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+    pub fn scan_token(&mut self) -> Token<'a> {
+        if let Err(error) = self.skip_whitespace() {
+            return error;
+        }
         self.start = self.current;
 
         if self.is_at_end() {
+            if !self.interpolations.is_empty() {
+                self.interpolations.clear();
+                return self.unterminated_token("Unterminated string interpolation.");
+            }
             return self.make_token(TT::Eof);
         }
 
         let c = self.advance();
 
-        return match c {
+        match c {
             '(' => self.make_token(TT::LeftParen),
             ')' => self.make_token(TT::RightParen),
-            '{' => self.make_token(TT::LeftBrace),
-            '}' => self.make_token(TT::RightBrace),
+            '{' => {
+                if let Some(braces) = self.interpolations.last_mut() {
+                    *braces += 1;
+                }
+                self.make_token(TT::LeftBrace)
+            }
+            '[' => self.make_token(TT::LeftBracket),
+            ']' => self.make_token(TT::RightBracket),
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string_token()
+                }
+                Some(braces) => {
+                    *braces -= 1;
+                    self.make_token(TT::RightBrace)
+                }
+                None => self.make_token(TT::RightBrace),
+            },
             ';' => self.make_token(TT::Semicolon),
+            ':' => self.make_token(TT::Colon),
             ',' => self.make_token(TT::Comma),
             '.' => self.make_token(TT::Dot),
             '-' => self.make_token(TT::Minus),
             '+' => self.make_token(TT::Plus),
             '/' => self.make_token(TT::Slash),
             '*' => self.make_token(TT::Star),
+            '%' => self.make_token(TT::Percent),
+            '?' => self.make_token(TT::Question),
             '!' => self.long_op_token('=', TT::BangEqual, TT::Bang),
             '=' => self.long_op_token('=', TT::EqualEqual, TT::Equal),
             '<' => self.long_op_token('=', TT::LessEqual, TT::Less),
             '>' => self.long_op_token('=', TT::GreaterEqual, TT::Greater),
             '"' => self.string_token(),
+            'r' if matches!(self.peek(), '"' | '#') => self.raw_string_token(),
             '0'..='9' => self.number_token(),
-            is_alpha => self.identifier_token(),
+            c if is_alpha(c) => self.identifier_token(),
             _ => self.error_token("Unexpected character."),
-        };
+        }
     }
 
-    fn get_char(&mut self, idx: usize) -> char {
-        self.source.chars().nth(idx).unwrap()
+    /// The character at byte offset `idx`, or `'\0'` past the end.
+    fn char_at(&self, idx: usize) -> char {
+        self.source
+            .get(idx..)
+            .and_then(|rest| rest.chars().next())
+            .unwrap_or('\0')
     }
 
-    fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.get_char(self.current)
+    fn peek(&self) -> char {
+        self.char_at(self.current)
     }
 
-    fn peek_next(&mut self) -> char {
+    fn peek_next(&self) -> char {
         if self.is_at_end() {
             return '\0';
         }
-        self.get_char(self.current + 1)
+        self.char_at(self.current + self.peek().len_utf8())
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.get_char(self.current - 1)
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
     }
 
     fn either(&mut self, expected: char, opt_a: TT, opt_b: TT) -> TT {
@@ -142,10 +197,12 @@ impl Scanner<'_> {
             return opt_b;
         }
         self.current += 1;
-        return opt_a;
+        opt_a
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace and comments. An unterminated block comment is
+    /// returned as an error token.
+    fn skip_whitespace(&mut self) -> Result<(), Token<'a>> {
         loop {
             match self.peek() {
                 ' ' | '\r' | '\t' => {
@@ -154,21 +211,46 @@ impl Scanner<'_> {
                 '\n' => {
                     self.new_line();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                '/' if self.peek_next() == '/' => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
-                _ => return,
+                '/' if self.peek_next() == '*' => self.block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Skips a `/* ... */` comment. Block comments nest, so a commented out
+    /// piece of code may contain comments of its own.
+    fn block_comment(&mut self) -> Result<(), Token<'a>> {
+        // The opening "/*".
+        self.advance();
+        self.advance();
+
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(self.unterminated_token("Unterminated block comment."));
+            }
+            match self.advance() {
+                '/' if self.peek() == '*' => {
+                    self.advance();
+                    depth += 1;
+                }
+                '*' if self.peek() == '/' => {
+                    self.advance();
+                    depth -= 1;
+                }
+                '\n' => self.line += 1,
+                _ => {}
             }
         }
+        Ok(())
     }
 
-    fn make_token(&self, typ: TT) -> Token {
+    fn make_token(&self, typ: TT) -> Token<'a> {
         Token {
             typ,
             data: &self.source[self.start..self.current],
@@ -176,7 +258,7 @@ impl Scanner<'_> {
         }
     }
 
-    fn error_token(&self, msg: &str) -> Token {
+    fn error_token(&self, msg: &'static str) -> Token<'a> {
         Token {
             typ: TT::Error,
             data: msg,
@@ -184,71 +266,214 @@ impl Scanner<'_> {
         }
     }
 
-    fn long_op_token(&mut self, expected: char, opt_a: TT, opt_b: TT) -> Token {
+    fn unterminated_token(&mut self, msg: &'static str) -> Token<'a> {
+        self.unterminated = true;
+        self.error_token(msg)
+    }
+
+    fn long_op_token(&mut self, expected: char, opt_a: TT, opt_b: TT) -> Token<'a> {
         let token_type = self.either(expected, opt_a, opt_b);
-        return self.make_token(token_type);
+        self.make_token(token_type)
     }
 
-    fn string_token(&mut self) -> Token {
+    /// Scans a string literal, or the part of one up to the next `${`.
+    /// Each part is its own token: the string starts with `"` or, after an
+    /// interpolated expression, with the `}` that closed it, and ends with
+    /// `"` for a `String` or with `${` for an `Interpolation`.
+    fn string_token(&mut self) -> Token<'a> {
         while self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.new_line()
-            }
             if self.is_at_end() {
-                return self.error_token("Unterminated string.");
+                return self.unterminated_token("Unterminated string.");
+            }
+            if self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                return self.make_token(TT::Interpolation);
+            }
+            let mut c = self.advance();
+            if c == '\\' && !self.is_at_end() {
+                // The compiler decodes the escape, here it only must not
+                // end the string.
+                c = self.advance();
+            }
+            if c == '\n' {
+                self.line += 1;
             }
-            self.advance();
         }
         // The closing quote.
         self.advance();
-        return self.make_token(TT::String);
+        self.make_token(TT::String)
     }
 
-    fn number_token(&mut self) -> Token {
-        while is_digit(self.peek()) {
+    /// `r"..."` takes the text between the quotes as it is, without
+    /// escapes. Hashes after the `r` must be repeated after the closing
+    /// quote, so `r#"say "hi""#` can contain quotes.
+    fn raw_string_token(&mut self) -> Token<'a> {
+        let mut hashes = 0;
+        while self.peek() == '#' {
             self.advance();
+            hashes += 1;
+        }
+        if self.peek() != '"' {
+            return self.error_token("Expect '\"' to start a raw string.");
         }
+        self.advance();
+
+        loop {
+            if self.is_at_end() {
+                return self.unterminated_token("Unterminated raw string.");
+            }
+            match self.advance() {
+                '\n' => self.line += 1,
+                '"' if self.closes_raw_string(hashes) => {
+                    self.current += hashes;
+                    return self.make_token(TT::RawString);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn closes_raw_string(&self, hashes: usize) -> bool {
+        let rest = &self.source.as_bytes()[self.current..];
+        rest.len() >= hashes && rest[..hashes].iter().all(|&b| b == b'#')
+    }
+
+    fn number_token(&mut self) -> Token<'a> {
+        match self.number() {
+            Ok(()) => self.make_token(TT::Number),
+            Err(error) => error,
+        }
+    }
+
+    /// Scans the rest of a number literal: `123`, `1_000`, `1.5e-3`, or a
+    /// `0x1F` hex or `0b1010` binary integer. The compiler converts the
+    /// lexeme to a number.
+    fn number(&mut self) -> Result<(), Token<'a>> {
+        if self.char_at(self.start) == '0' {
+            match self.peek() {
+                'x' => return self.radix_number(16, "Expect hex digits after '0x'."),
+                'b' => return self.radix_number(2, "Expect binary digits after '0b'."),
+                _ => {}
+            }
+        }
+        self.digits(10)?;
 
         // Look for a fractional part.
         if self.peek() == '.' && is_digit(self.peek_next()) {
             // Consume the ".".
             self.advance();
+            self.digits(10)?;
+        }
 
-            while is_digit(self.peek()) {
+        if matches!(self.peek(), 'e' | 'E') {
+            self.advance();
+            if matches!(self.peek(), '+' | '-') {
                 self.advance();
             }
+            if !is_digit(self.peek()) {
+                return Err(self.error_token("Expect digits in exponent."));
+            }
+            self.digits(10)?;
         }
+        Ok(())
+    }
 
-        return self.make_token(TT::Number);
+    fn radix_number(&mut self, radix: u32, missing_digits: &'static str) -> Result<(), Token<'a>> {
+        // The "x" or "b".
+        self.advance();
+        if !self.peek().is_digit(radix) {
+            return Err(self.error_token(missing_digits));
+        }
+        self.digits(radix)?;
+        if is_alpha(self.peek()) || is_digit(self.peek()) {
+            return Err(self.error_token("Invalid digit in number."));
+        }
+        Ok(())
     }
 
-    fn identifier_token(&mut self) -> Token {
+    /// Consumes digits in `radix`, which may be separated by single
+    /// underscores.
+    fn digits(&mut self, radix: u32) -> Result<(), Token<'a>> {
+        loop {
+            match self.peek() {
+                '_' => {
+                    self.advance();
+                    if !self.peek().is_digit(radix) {
+                        return Err(self.error_token("Expect digit after '_' in number."));
+                    }
+                }
+                c if c.is_digit(radix) => {
+                    self.advance();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn identifier_token(&mut self) -> Token<'a> {
         while is_alpha(self.peek()) || is_digit(self.peek()) {
             self.advance();
         }
 
-        return self.make_token(self.identifier_type());
+        let typ = self.identifier_type();
+        self.make_token(typ)
     }
 
-    fn identifier_type(&mut self) -> TT {
-        return match self.get_char(self.start) {
-            'a' => self.check_keyword(1, 2, "nd", TT::And),
-            'c' => self.check_keyword(1, 4, "lass", TT::Class),
-            'e' => self.check_keyword(1, 3, "lse", TT::Else),
-            'i' => self.check_keyword(1, 1, "f", TT::If),
-            'n' => self.check_keyword(1, 2, "il", TT::Nil),
-            'o' => self.check_keyword(1, 1, "r", TT::Or),
-            'p' => self.check_keyword(1, 4, "rint", TT::Print),
-            'r' => self.check_keyword(1, 5, "eturn", TT::Return),
-            's' => self.check_keyword(1, 4, "uper", TT::Super),
-            'v' => self.check_keyword(1, 2, "ar", TT::Var),
-            'w' => self.check_keyword(1, 4, "hile", TT::While),
+    /// Tells keywords from other identifiers with a trie of nested matches
+    /// on the leading characters, which leaves at most one comparison
+    /// with the rest of a keyword.
+    fn identifier_type(&self) -> TT {
+        match self.char_at(self.start) {
+            'a' => self.check_keyword(1, "nd", TT::And),
+            'c' if self.current - self.start > 1 => match self.char_at(self.start + 1) {
+                'a' => self.check_keyword(2, "tch", TT::Catch),
+                'l' => self.check_keyword(2, "ass", TT::Class),
+                _ => TT::Identifier,
+            },
+            'e' => self.check_keyword(1, "lse", TT::Else),
+            'f' if self.current - self.start > 1 => match self.char_at(self.start + 1) {
+                'a' => self.check_keyword(2, "lse", TT::False),
+                'i' => self.check_keyword(2, "nally", TT::Finally),
+                'o' => self.check_keyword(2, "r", TT::For),
+                'u' => self.check_keyword(2, "n", TT::Fun),
+                _ => TT::Identifier,
+            },
+            'i' if self.current - self.start > 1 => match self.char_at(self.start + 1) {
+                'f' => self.check_keyword(2, "", TT::If),
+                'n' => self.check_keyword(2, "", TT::In),
+                _ => TT::Identifier,
+            },
+            'n' => self.check_keyword(1, "il", TT::Nil),
+            'o' => self.check_keyword(1, "r", TT::Or),
+            'p' => self.check_keyword(1, "rint", TT::Print),
+            'r' => self.check_keyword(1, "eturn", TT::Return),
+            's' => self.check_keyword(1, "uper", TT::Super),
+            't' if self.current - self.start > 1 => match self.char_at(self.start + 1) {
+                'h' if self.current - self.start > 2 => match self.char_at(self.start + 2) {
+                    'i' => self.check_keyword(3, "s", TT::This),
+                    'r' => self.check_keyword(3, "ow", TT::Throw),
+                    _ => TT::Identifier,
+                },
+                'r' if self.current - self.start > 2 => match self.char_at(self.start + 2) {
+                    'u' => self.check_keyword(3, "e", TT::True),
+                    'y' => self.check_keyword(3, "", TT::Try),
+                    _ => TT::Identifier,
+                },
+                _ => TT::Identifier,
+            },
+            'v' => self.check_keyword(1, "ar", TT::Var),
+            'w' => self.check_keyword(1, "hile", TT::While),
             _ => TT::Identifier,
-        };
+        }
     }
 
-    fn check_keyword(&mut self, start: usize, len: usize, rest: &str, tt: TT) -> TT {
-        if &self.source[self.start + start..len] == rest {
+    /// The identifier is the keyword `tt` if its characters from `start`
+    /// on are exactly `rest`. The first `start` characters have already
+    /// been matched by `identifier_type`.
+    fn check_keyword(&self, start: usize, rest: &str, tt: TT) -> TT {
+        if &self.source[self.start + start..self.current] == rest {
             tt
         } else {
             TT::Identifier
@@ -256,7 +481,7 @@ impl Scanner<'_> {
     }
 
     fn is_at_end(&self) -> bool {
-        self.current == self.source.chars().count()
+        self.current >= self.source.len()
     }
 
     fn new_line(&mut self) {
@@ -265,31 +490,57 @@ impl Scanner<'_> {
     }
 }
 
+/// Tells whether `source` is ready to be compiled: every opened brace,
+/// bracket and parenthesis has been closed and no string literal,
+/// interpolation or block comment is left open at the end.
+/// Unbalanced closing tokens are left for the compiler to report.
+pub fn is_complete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0;
+
+    loop {
+        let token = scanner.scan_token();
+        match token.typ {
+            TT::LeftParen | TT::LeftBrace | TT::LeftBracket => depth += 1,
+            TT::RightParen | TT::RightBrace | TT::RightBracket => depth -= 1,
+            TT::Error if scanner.unterminated => return false,
+            TT::Eof => return depth <= 0,
+            _ => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn check_basics() {
-        let mut s = Scanner::new(&"(){};,.-+/*");
+        let mut s = Scanner::new("(){}[];:,.-+/ *%?");
 
         assert_eq!(s.scan_token().typ, TT::LeftParen);
         assert_eq!(s.scan_token().typ, TT::RightParen);
         assert_eq!(s.scan_token().typ, TT::LeftBrace);
         assert_eq!(s.scan_token().typ, TT::RightBrace);
+        assert_eq!(s.scan_token().typ, TT::LeftBracket);
+        assert_eq!(s.scan_token().typ, TT::RightBracket);
         assert_eq!(s.scan_token().typ, TT::Semicolon);
+        assert_eq!(s.scan_token().typ, TT::Colon);
         assert_eq!(s.scan_token().typ, TT::Comma);
         assert_eq!(s.scan_token().typ, TT::Dot);
         assert_eq!(s.scan_token().typ, TT::Minus);
         assert_eq!(s.scan_token().typ, TT::Plus);
         assert_eq!(s.scan_token().typ, TT::Slash);
         assert_eq!(s.scan_token().typ, TT::Star);
+        assert_eq!(s.scan_token().typ, TT::Percent);
+        assert_eq!(s.scan_token().typ, TT::Question);
         assert_eq!(s.scan_token().typ, TT::Eof);
     }
 
     #[test]
     fn check_long_ops() {
-        let mut s = Scanner::new(&"! != = == < <= > >= ");
+        let mut s = Scanner::new("! != = == < <= > >= ");
 
         assert_eq!(s.scan_token().typ, TT::Bang);
         assert_eq!(s.scan_token().typ, TT::BangEqual);
@@ -304,23 +555,73 @@ mod tests {
 
     #[test]
     fn check_whitespace() {
-        let mut s = Scanner::new(&" \t\n");
+        let mut s = Scanner::new(" \t\n");
 
         assert_eq!(s.scan_token().typ, TT::Eof);
     }
 
     #[test]
     fn check_single_line_comment() {
-        let mut s = Scanner::new(&"; // hello world\n ;");
+        let mut s = Scanner::new("; // hello world\n ;");
 
         assert_eq!(s.scan_token().typ, TT::Semicolon);
         assert_eq!(s.scan_token().typ, TT::Semicolon);
         assert_eq!(s.scan_token().typ, TT::Eof);
     }
 
+    #[test]
+    fn check_block_comments() {
+        let mut s = Scanner::new("1 /* one\n /* two\n */ still */ 2 /**/ 3 /*/ 4 */ 5");
+
+        let token = s.scan_token();
+        assert_eq!((token.data, token.line), ("1", 1));
+        let token = s.scan_token();
+        assert_eq!((token.data, token.line), ("2", 3));
+        assert_eq!(s.scan_token().data, "3");
+        assert_eq!(s.scan_token().data, "5");
+        assert_eq!(s.scan_token().typ, TT::Eof);
+
+        let mut s = Scanner::new("1 /* a /* b */\n");
+        assert_eq!(s.scan_token().data, "1");
+        let token = s.scan_token();
+        assert_eq!(
+            (token.typ, token.data, token.line),
+            (TT::Error, "Unterminated block comment.", 2)
+        );
+        assert_eq!(s.scan_token().typ, TT::Eof);
+    }
+
+    #[test]
+    fn check_escaped_quotes() {
+        let mut s = Scanner::new(r#""say \"hi\"" "\\" "\"#);
+
+        assert_eq!(s.scan_token().data, r#""say \"hi\"""#);
+        assert_eq!(s.scan_token().data, r#""\\""#);
+        assert_eq!(s.scan_token().data, "Unterminated string.");
+    }
+
+    #[test]
+    fn check_raw_strings() {
+        let mut s = Scanner::new("r\"C:\\dir\" r#\"a \"quoted\"\n word\"# r##\"\"#\"## r x");
+
+        let token = s.scan_token();
+        assert_eq!((token.typ, token.data), (TT::RawString, "r\"C:\\dir\""));
+        let token = s.scan_token();
+        assert_eq!((token.typ, token.line), (TT::RawString, 2));
+        assert_eq!(token.data, "r#\"a \"quoted\"\n word\"#");
+        assert_eq!(s.scan_token().data, "r##\"\"#\"##");
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+
+        let mut s = Scanner::new("r#\"open\"");
+        assert_eq!(s.scan_token().data, "Unterminated raw string.");
+        let mut s = Scanner::new("r#x");
+        assert_eq!(s.scan_token().data, "Expect '\"' to start a raw string.");
+    }
+
     #[test]
     fn check_string() {
-        let mut s = Scanner::new(&r###";"hello \n worl";"###);
+        let mut s = Scanner::new(r###";"hello \n worl";"###);
 
         assert_eq!(s.scan_token().typ, TT::Semicolon);
         assert_eq!(s.scan_token().typ, TT::String);
@@ -328,24 +629,259 @@ mod tests {
         assert_eq!(s.scan_token().typ, TT::Eof);
     }
 
+    #[test]
+    fn check_interpolation() {
+        let mut s = Scanner::new(r#""a ${b} c ${ {1: "${d}"}[1] }" } "\${""#);
+
+        let mut expect = |typ: TT, data: &str| {
+            let token = s.scan_token();
+            assert_eq!((token.typ, token.data), (typ, data));
+        };
+        expect(TT::Interpolation, "\"a ${");
+        expect(TT::Identifier, "b");
+        expect(TT::Interpolation, "} c ${");
+        expect(TT::LeftBrace, "{");
+        expect(TT::Number, "1");
+        expect(TT::Colon, ":");
+        expect(TT::Interpolation, "\"${");
+        expect(TT::Identifier, "d");
+        expect(TT::String, "}\"");
+        expect(TT::RightBrace, "}");
+        expect(TT::LeftBracket, "[");
+        expect(TT::Number, "1");
+        expect(TT::RightBracket, "]");
+        expect(TT::String, "}\"");
+        expect(TT::RightBrace, "}");
+        expect(TT::String, "\"\\${\"");
+        expect(TT::Eof, "");
+
+        let mut s = Scanner::new("\"${a");
+        assert_eq!(s.scan_token().typ, TT::Interpolation);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().data, "Unterminated string interpolation.");
+        assert_eq!(s.scan_token().typ, TT::Eof);
+    }
+
+    #[test]
+    fn check_multiline_string() {
+        let mut s = Scanner::new("\"a\nb\" c");
+
+        let token = s.scan_token();
+        assert_eq!(
+            (token.typ, token.data, token.line),
+            (TT::String, "\"a\nb\"", 2)
+        );
+        let token = s.scan_token();
+        assert_eq!(
+            (token.typ, token.data, token.line),
+            (TT::Identifier, "c", 2)
+        );
+    }
+
+    #[test]
+    fn check_non_ascii() {
+        let mut s = Scanner::new("\"héllo\" é; \"ü");
+
+        assert_eq!(s.scan_token().data, "\"héllo\"");
+        let token = s.scan_token();
+        assert_eq!(
+            (token.typ, token.data),
+            (TT::Error, "Unexpected character.")
+        );
+        assert_eq!(s.scan_token().typ, TT::Semicolon);
+        assert_eq!(s.scan_token().data, "Unterminated string.");
+        assert_eq!(s.scan_token().typ, TT::Eof);
+    }
+
+    #[test]
+    fn check_ends_without_panicking() {
+        for source in &["/", "1 /", "1.", "\"", "a\u{0}", "é", "//é"] {
+            let mut s = Scanner::new(source);
+            while s.scan_token().typ != TT::Eof {}
+        }
+    }
+
     #[test]
     fn check_invalid_string() {
         let expected_error = "Unterminated string.";
-        let mut s = Scanner::new(&r###""hello "###);
-        assert_eq!(s.scan_token().typ, TT::Error);
-        let error = s.scan_token().data;
+        let mut s = Scanner::new(r###""hello "###);
+        let token = s.scan_token();
+        assert_eq!(token.typ, TT::Error);
+        assert_eq!(token.data, expected_error);
+    }
+
+    #[test]
+    fn check_keywords() {
+        let mut s = Scanner::new("false for fun this true fo classy if in i inner");
+
+        assert_eq!(s.scan_token().typ, TT::False);
+        assert_eq!(s.scan_token().typ, TT::For);
+        assert_eq!(s.scan_token().typ, TT::Fun);
+        assert_eq!(s.scan_token().typ, TT::This);
+        assert_eq!(s.scan_token().typ, TT::True);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().typ, TT::If);
+        assert_eq!(s.scan_token().typ, TT::In);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().typ, TT::Identifier);
+        assert_eq!(s.scan_token().typ, TT::Eof);
+
+        let mut s = Scanner::new("try catch finally throw tr th thro cat");
+        assert_eq!(s.scan_token().typ, TT::Try);
+        assert_eq!(s.scan_token().typ, TT::Catch);
+        assert_eq!(s.scan_token().typ, TT::Finally);
+        assert_eq!(s.scan_token().typ, TT::Throw);
+        for _ in 0..4 {
+            assert_eq!(s.scan_token().typ, TT::Identifier);
+        }
+        assert_eq!(s.scan_token().typ, TT::Eof);
+    }
+
+    const KEYWORDS: [(&str, TT); 21] = [
+        ("and", TT::And),
+        ("catch", TT::Catch),
+        ("class", TT::Class),
+        ("else", TT::Else),
+        ("false", TT::False),
+        ("finally", TT::Finally),
+        ("for", TT::For),
+        ("fun", TT::Fun),
+        ("if", TT::If),
+        ("in", TT::In),
+        ("nil", TT::Nil),
+        ("or", TT::Or),
+        ("print", TT::Print),
+        ("return", TT::Return),
+        ("super", TT::Super),
+        ("this", TT::This),
+        ("throw", TT::Throw),
+        ("true", TT::True),
+        ("try", TT::Try),
+        ("var", TT::Var),
+        ("while", TT::While),
+    ];
+
+    fn single_token(source: &str) -> TT {
+        let mut s = Scanner::new(source);
+        let typ = s.scan_token().typ;
+        assert_eq!(s.scan_token().typ, TT::Eof, "{:?} is one token", source);
+        typ
+    }
+
+    #[test]
+    fn keywords_scan_as_themselves() {
+        for (keyword, typ) in KEYWORDS.iter() {
+            assert_eq!(single_token(keyword), *typ);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn extended_keywords_are_identifiers(
+            idx in 0..KEYWORDS.len(),
+            prefix in "[a-zA-Z_]?[a-zA-Z0-9_]{0,3}",
+            suffix in "[a-zA-Z0-9_]{0,4}",
+        ) {
+            let keyword = KEYWORDS[idx].0;
+            prop_assume!(!prefix.is_empty() || !suffix.is_empty());
+            prop_assume!(prefix.is_empty() || !prefix.starts_with(|c: char| c.is_ascii_digit()));
+            let identifier = format!("{}{}{}", prefix, keyword, suffix);
+            // "f" + "or" and "f" + "un" make other keywords.
+            prop_assume!(KEYWORDS.iter().all(|(other, _)| *other != identifier));
+            prop_assert_eq!(single_token(&identifier), TT::Identifier);
+        }
+
+        #[test]
+        fn truncated_keywords_are_identifiers(idx in 0..KEYWORDS.len(), cut in 1usize..7) {
+            let keyword = KEYWORDS[idx].0;
+            prop_assume!(cut < keyword.len());
+            let truncated = &keyword[..keyword.len() - cut];
+            let expected = KEYWORDS
+                .iter()
+                .find(|(other, _)| *other == truncated)
+                .map_or(TT::Identifier, |(_, typ)| *typ);
+            prop_assert_eq!(single_token(truncated), expected);
+        }
+
+        #[test]
+        fn other_names_are_identifiers(name in "[a-zA-Z_][a-zA-Z0-9_]{0,8}") {
+            prop_assume!(KEYWORDS.iter().all(|(keyword, _)| *keyword != name));
+            prop_assert_eq!(single_token(&name), TT::Identifier);
+        }
     }
 
     #[test]
     fn check_number() {
-        let mut s = Scanner::new(&"123");
+        let mut s = Scanner::new("123");
 
         assert_eq!(s.scan_token().typ, TT::Number);
         assert_eq!(s.scan_token().typ, TT::Eof);
 
-        let mut s2 = Scanner::new(&"123.42");
+        let mut s2 = Scanner::new("123.42");
 
         assert_eq!(s2.scan_token().typ, TT::Number);
         assert_eq!(s2.scan_token().typ, TT::Eof);
+
+        let mut s3 = Scanner::new("0x1F 0b1010 1_000_000 1.5e-3 2E+8 3e4 0xdead_beef 1.");
+        for lexeme in &[
+            "0x1F",
+            "0b1010",
+            "1_000_000",
+            "1.5e-3",
+            "2E+8",
+            "3e4",
+            "0xdead_beef",
+            "1",
+        ] {
+            let token = s3.scan_token();
+            assert_eq!((token.typ, token.data), (TT::Number, *lexeme));
+        }
+        assert_eq!(s3.scan_token().typ, TT::Dot);
+        assert_eq!(s3.scan_token().typ, TT::Eof);
+    }
+
+    #[test]
+    fn check_invalid_number() {
+        for (source, message) in &[
+            ("0x", "Expect hex digits after '0x'."),
+            ("0x_1", "Expect hex digits after '0x'."),
+            ("0b", "Expect binary digits after '0b'."),
+            ("0b12", "Invalid digit in number."),
+            ("0xfg", "Invalid digit in number."),
+            ("1_", "Expect digit after '_' in number."),
+            ("1__0", "Expect digit after '_' in number."),
+            ("1.5_", "Expect digit after '_' in number."),
+            ("1e", "Expect digits in exponent."),
+            ("1e+", "Expect digits in exponent."),
+            ("2.5E-x", "Expect digits in exponent."),
+        ] {
+            let token = Scanner::new(source).scan_token();
+            assert_eq!((token.typ, token.data), (TT::Error, *message), "{}", source);
+        }
+    }
+
+    #[test]
+    fn check_complete_input() {
+        assert!(is_complete("1 + 2;\n"));
+        assert!(is_complete("(1 + 2) * 3;\n"));
+        assert!(is_complete("\n"));
+        assert!(is_complete("1);\n"));
+        // Other scanner errors are left for the compiler to report.
+        assert!(is_complete("@;\n"));
+        assert!(is_complete("0x;\n"));
+    }
+
+    #[test]
+    fn check_incomplete_input() {
+        assert!(!is_complete("(1 +\n"));
+        assert!(!is_complete("{\n"));
+        assert!(!is_complete("{ (1)\n"));
+        assert!(!is_complete("var a = [1,\n"));
+        assert!(!is_complete("\"hello\n"));
+        assert!(!is_complete("r#\"hello\"\n"));
+        assert!(!is_complete("/* /* */\n"));
+        assert!(!is_complete("print \"a ${\n"));
+        assert!(!is_complete("print \"a ${ {1: 2}\n"));
     }
 }
@@ -1,7 +1,5 @@
 use std::slice::Iter;
 
-const STACK_LIMIT: usize = 128;
-
 #[derive(Debug)]
 pub struct Stack<T> {
     size: usize,
@@ -10,9 +8,6 @@ pub struct Stack<T> {
 
 impl<T> Stack<T> {
     pub fn new(size: usize) -> Stack<T> {
-        if size > 1024 {
-            panic!("Stack can not bigger then 1024")
-        }
         Stack {
             size,
             list: Vec::new(),
@@ -30,7 +25,92 @@ impl<T> Stack<T> {
         self.list.pop().unwrap()
     }
 
+    /// Returns the item `distance` slots down from the top of the stack.
+    pub fn peek(&self, distance: usize) -> &T {
+        &self.list[self.list.len() - 1 - distance]
+    }
+
+    pub fn get(&self, idx: usize) -> &T {
+        &self.list[idx]
+    }
+
+    pub fn set(&mut self, idx: usize, value: T) {
+        self.list[idx] = value
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Returns the `count` items on top of the stack, bottom first.
+    pub fn top(&self, count: usize) -> &[T] {
+        &self.list[self.list.len() - count..]
+    }
+
+    /// Drops every item above the first `len`.
+    pub fn truncate(&mut self, len: usize) {
+        self.list.truncate(len)
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear()
+    }
+
     pub fn iter(&self) -> Iter<'_, T> {
         self.list.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut stack = Stack::new(4);
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+
+        assert_eq!(*stack.peek(0), 3);
+        assert_eq!(*stack.peek(2), 1);
+        assert_eq!(stack.pop(), 3);
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn get_and_set_slots() {
+        let mut stack = Stack::new(4);
+        stack.push(1);
+        stack.push(2);
+        stack.set(0, 42);
+
+        assert_eq!(*stack.get(0), 42);
+        assert_eq!(*stack.get(1), 2);
+    }
+
+    #[test]
+    fn top_and_truncate() {
+        let mut stack = Stack::new(4);
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+
+        assert_eq!(stack.top(2), &[2, 3]);
+        stack.truncate(1);
+        assert_eq!(stack.len(), 1);
+        assert_eq!(*stack.peek(0), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Stack limit reached")]
+    fn overflow() {
+        let mut stack = Stack::new(1);
+        stack.push(1);
+        stack.push(2);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::object::LoxString;
+use crate::value::Value;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Hash table of values. Keyed by strings it holds the global variables of
+/// the VM, stored the way the VM stack stores them; keyed by
+/// [`Key`](crate::value::Key) it backs Lox maps.
+#[derive(Debug)]
+pub struct Table<K = LoxString, V = Value> {
+    entries: HashMap<K, V>,
+}
+
+impl<K: Hash + Eq, V> Default for Table<K, V> {
+    fn default() -> Table<K, V> {
+        Table {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Table<K, V> {
+    pub fn new() -> Table<K, V> {
+        Table::default()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.get(key)
+    }
+
+    /// Adds or replaces the entry, returns `true` when the key is new.
+    pub fn set(&mut self, key: K, value: V) -> bool {
+        self.entries.insert(key, value).is_none()
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    /// Removes the entry, returns `true` when there was one.
+    pub fn delete<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.remove(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the entries in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter()
+    }
+
+    /// Removes every entry, yielding the values.
+    pub(crate) fn drain_values(&mut self) -> impl Iterator<Item = V> + '_ {
+        self.entries.drain().map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let mut table = Table::new();
+
+        assert!(table.set(LoxString::from("a"), Value::Number(1.0)));
+        assert!(!table.set(LoxString::from("a"), Value::Number(2.0)));
+        assert_eq!(table.get("a"), Some(&Value::Number(2.0)));
+        assert!(table.contains("a"));
+        assert!(!table.contains("b"));
+        assert_eq!(table.get("b"), None);
+    }
+
+    #[test]
+    fn delete() {
+        let mut table: Table = Table::new();
+        table.set(LoxString::from("a"), Value::Nil);
+
+        assert!(table.delete("a"));
+        assert!(!table.delete("a"));
+        assert!(table.is_empty());
+        assert_eq!(table.len(), 0);
+    }
+}
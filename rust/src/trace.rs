@@ -0,0 +1,160 @@
+use crate::chunk::Chunk;
+use crate::debug;
+use crate::stack::Stack;
+use crate::value::Slot;
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Runtime instruction tracing. Every executed instruction that passes the
+/// filters is written to the sink as one line: offset, source line, the
+/// instruction and the value stack before the instruction runs.
+pub struct Trace {
+    out: Box<dyn Write>,
+    function: Option<String>,
+    lines: Option<RangeInclusive<usize>>,
+}
+
+impl Trace {
+    pub fn new(out: Box<dyn Write>) -> Trace {
+        Trace {
+            out,
+            function: None,
+            lines: None,
+        }
+    }
+
+    /// Only trace the instructions of the function called `name`.
+    pub fn function(mut self, name: &str) -> Trace {
+        self.function = Some(name.to_string());
+        self
+    }
+
+    /// Only trace the instructions compiled from the given source lines.
+    pub fn lines(mut self, lines: RangeInclusive<usize>) -> Trace {
+        self.lines = Some(lines);
+        self
+    }
+
+    pub fn instruction(
+        &mut self,
+        function: &str,
+        chunk: &Chunk,
+        offset: usize,
+        stack: &Stack<Slot>,
+    ) {
+        let line = chunk.lines.get_line(offset);
+        if !self.matches(function, line) {
+            return;
+        }
+
+        let (text, _) = debug::instruction(chunk, offset);
+        let mut slots = String::new();
+        for value in stack.iter() {
+            slots.push_str(&format!("[ {} ]", value));
+        }
+        // A broken trace sink must not abort the script being traced.
+        let _ = writeln!(self.out, "{:04} {:4} {:<32} {}", offset, line, text, slots);
+    }
+
+    fn matches(&self, function: &str, line: usize) -> bool {
+        let function_matches = match &self.function {
+            Some(name) => name == function,
+            None => true,
+        };
+        let line_matches = match &self.lines {
+            Some(lines) => lines.contains(&line),
+            None => true,
+        };
+        function_matches && line_matches
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("function", &self.function)
+            .field("lines", &self.lines)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+    use crate::vm::Vm;
+
+    fn run_traced(source: &str, trace: impl FnOnce(Trace) -> Trace) -> Vec<String> {
+        // Constant folding would leave little to trace.
+        run_optimized_traced(source, false, trace)
+    }
+
+    fn run_optimized_traced(
+        source: &str,
+        optimize: bool,
+        trace: impl FnOnce(Trace) -> Trace,
+    ) -> Vec<String> {
+        let capture = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_optimize(optimize);
+        vm.set_stdout(Box::new(Capture::new()));
+        vm.set_trace(Some(trace(Trace::new(Box::new(capture.clone())))));
+        let _ = vm.interpret(source);
+        capture
+            .contents()
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn trace_every_instruction() {
+        let lines = run_traced("1 + 2;", |trace| trace);
+
+        assert_eq!(
+            lines,
+            vec![
+                "0000    1 OP_CONSTANT         0 1          [ <script> ]",
+                "0002    1 OP_CONSTANT         1 2          [ <script> ][ 1 ]",
+                "0004    1 OP_ADD                           [ <script> ][ 1 ][ 2 ]",
+                "0005    1 OP_RETURN                        [ <script> ][ 3 ]",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_line_range() {
+        let lines = run_traced("1 +\n2 +\n3;", |trace| trace.lines(2..=2));
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0002    2 OP_CONSTANT"));
+        assert!(lines[1].starts_with("0004    2 OP_ADD"));
+    }
+
+    #[test]
+    fn trace_function_filter() {
+        assert_eq!(run_traced("1;", |trace| trace.function("script")).len(), 2);
+        assert!(run_traced("1;", |trace| trace.function("fib")).is_empty());
+
+        let source = "fun fib(n) { return n; }\nfib(1);";
+        let lines = run_traced(source, |trace| trace.function("fib"));
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0000    1 OP_GET_LOCAL        1"));
+    }
+
+    #[test]
+    fn trace_superinstructions() {
+        let source = "fun inc(a) { return a + 1; }\ninc(1);";
+        let lines = run_optimized_traced(source, true, |trace| trace.function("inc"));
+
+        assert_eq!(
+            lines,
+            vec![
+                "0000    1 OP_GET_LOCAL_1                   [ <script> ][ <fn inc> ][ 1 ]",
+                "0001    1 OP_ADD_CONSTANT     0 1          [ <script> ][ <fn inc> ][ 1 ][ 1 ]",
+                "0003    1 OP_RETURN                        [ <script> ][ <fn inc> ][ 1 ][ 2 ]",
+            ]
+        );
+    }
+}
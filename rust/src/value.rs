@@ -1,14 +1,435 @@
-use std::ops::Index;
+use crate::object::{Function, Iter, LoxString, Native};
+use crate::table::Table;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
 
-pub type Value = f64;
+/// How the VM stores a value in a stack slot, a global or a constant: the
+/// value itself, or packed into one word with the `nan-boxing` feature.
+#[cfg(not(feature = "nan-boxing"))]
+pub(crate) type Slot = Value;
+#[cfg(feature = "nan-boxing")]
+pub(crate) use crate::nanbox::NanBox as Slot;
 
-pub fn print_value(value: Value) {
-    print!("{}", value)
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(LoxString),
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+    Iter(Rc<RefCell<Iter>>),
 }
 
+impl Value {
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // With `nan-boxing` the VM reads slots through `NanBox` instead.
+    #[cfg_attr(feature = "nan-boxing", allow(dead_code))]
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[cfg_attr(feature = "nan-boxing", allow(dead_code))]
+    pub(crate) fn as_string(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// The name of the type of the value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Iter(_) => "iterator",
+        }
+    }
+}
+
+/// Strings compare by content, every other object by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Iter(a), Value::Iter(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+/// How many lists and maps `print` shows inside each other.
+const MAX_PRINT_DEPTH: usize = 256;
+
+impl Value {
+    /// Writes the value for `print`. `enclosing` holds the lists and maps
+    /// being written around it, so one that contains itself shows up as
+    /// `[...]` or `{...}` instead of recursing forever. So does one nested
+    /// more than `MAX_PRINT_DEPTH` deep, which would overflow the stack.
+    fn write(&self, f: &mut fmt::Formatter<'_>, enclosing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", format_number(*value)),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Function(function) => write!(f, "{}", function),
+            Value::Native(_) => write!(f, "<native fn>"),
+            Value::List(list) => {
+                let ptr = Rc::as_ptr(list) as *const ();
+                if enclosing.len() >= MAX_PRINT_DEPTH || enclosing.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                enclosing.push(ptr);
+                write!(f, "[")?;
+                for (idx, element) in list.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let ptr = Rc::as_ptr(map) as *const ();
+                if enclosing.len() >= MAX_PRINT_DEPTH || enclosing.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                enclosing.push(ptr);
+                write!(f, "{{")?;
+                for (idx, (key, value)) in sorted_entries(&map.borrow()).iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    value.write(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "}}")
+            }
+            Value::Iter(_) => write!(f, "<iterator>"),
+        }
+    }
+}
+
+impl From<&Value> for Value {
+    fn from(value: &Value) -> Value {
+        value.clone()
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(LoxString::from(value))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(LoxString::from(value))
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(List(elements))))
+    }
+}
+
+impl From<Iter> for Value {
+    fn from(iter: Iter) -> Value {
+        Value::Iter(Rc::new(RefCell::new(iter)))
+    }
+}
+
+impl From<Table<Key>> for Value {
+    fn from(entries: Table<Key>) -> Value {
+        Value::Map(Rc::new(RefCell::new(Map(entries))))
+    }
+}
+
+/// The elements of a Lox list. Dropping it drops the lists and maps
+/// nested in it one after another rather than recursively, so a deeply
+/// nested list does not overflow the stack.
+#[derive(Default)]
+pub struct List(Vec<Value>);
+
+impl Deref for List {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.0
+    }
+}
+
+impl DerefMut for List {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.0
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Drop for List {
+    fn drop(&mut self) {
+        drop_nested(self.0.drain(..));
+    }
+}
+
+/// The entries of a Lox map. Like a list, it drops the lists and maps
+/// nested in it without recursing.
+#[derive(Default)]
+pub struct Map(Table<Key>);
+
+impl Deref for Map {
+    type Target = Table<Key>;
+
+    fn deref(&self) -> &Table<Key> {
+        &self.0
+    }
+}
+
+impl DerefMut for Map {
+    fn deref_mut(&mut self) -> &mut Table<Key> {
+        &mut self.0
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        drop_nested(self.0.drain_values());
+    }
+}
+
+/// Drops `values`, taking apart the lists and maps only they refer to with
+/// a work list instead of the call stack.
+pub(crate) fn drop_nested(values: impl Iterator<Item = Value>) {
+    let mut pending = Vec::new();
+    defer_nested(values, &mut pending);
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::List(list) => {
+                if let Ok(list) = Rc::try_unwrap(list) {
+                    defer_nested(
+                        mem::take(&mut list.into_inner().0).into_iter(),
+                        &mut pending,
+                    );
+                }
+            }
+            Value::Map(map) => {
+                if let Ok(map) = Rc::try_unwrap(map) {
+                    defer_nested(map.into_inner().drain_values(), &mut pending);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Moves the values whose last reference is about to go and that hold
+/// other values to `pending`, and drops the rest.
+fn defer_nested(values: impl Iterator<Item = Value>, pending: &mut Vec<Value>) {
+    for value in values {
+        match &value {
+            Value::List(list) if Rc::strong_count(list) == 1 => pending.push(value),
+            Value::Map(map) if Rc::strong_count(map) == 1 => pending.push(value),
+            _ => drop(value),
+        }
+    }
+}
+
+/// A value that can be used as a map key: nil, a boolean, a number other
+/// than NaN, or a string.
+#[derive(Clone, Debug)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(LoxString),
+}
+
+impl Key {
+    /// Returns `None` for values that cannot be hashed.
+    pub fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Nil => Some(Key::Nil),
+            Value::Bool(value) => Some(Key::Bool(*value)),
+            Value::Number(value) if value.is_nan() => None,
+            // -0 and 0 are equal, so they must be the same key.
+            Value::Number(value) => Some(Key::Number(*value + 0.0)),
+            Value::String(value) => Some(Key::String(value.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Key::Nil => Value::Nil,
+            Key::Bool(value) => Value::Bool(*value),
+            Key::Number(value) => Value::Number(*value),
+            Key::String(value) => Value::String(value.clone()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Nil => 0,
+            Key::Bool(_) => 1,
+            Key::Number(_) => 2,
+            Key::String(_) => 3,
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Key::Nil => {}
+            Key::Bool(value) => value.hash(state),
+            Key::Number(value) => value.to_bits().hash(state),
+            Key::String(value) => value.hash(state),
+        }
+    }
+}
+
+/// Keys of different types order nil, booleans, numbers, strings.
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (Key::Bool(a), Key::Bool(b)) => a.cmp(b),
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::String(a), Key::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+/// The entries of a map ordered by key, the order maps are shown and
+/// iterated in.
+pub fn sorted_entries(map: &Table<Key>) -> Vec<(Key, Value)> {
+    let mut entries: Vec<(Key, Value)> = map
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+/// Formats a number the way `print`, `str()` and the disassembler show
+/// it. This matches the Kotlin interpreter: the shortest digits that read
+/// back as the same number, no `.0` on integers, and from 1e7 up or below
+/// 1e-3 scientific notation like `1.0E21` or `2.5E-4`. Dividing by zero
+/// under IEEE semantics gives `inf`, `-inf` and `nan`.
+pub fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        return "nan".to_string();
+    }
+    if number.is_infinite() {
+        return if number > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    let magnitude = number.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        // Rust already drops the `.0` and keeps the sign of -0.
+        return format!("{}", number);
+    }
+
+    let scientific = format!("{:e}", number);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("`{:e}` always writes an exponent");
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+pub fn print_value(out: &mut dyn Write, value: &Value) -> io::Result<()> {
+    write!(out, "{}", value)
+}
+
+/// The constants of a chunk, stored the way the VM stack stores values so
+/// that loading one only clones it.
 #[derive(Debug)]
 pub struct ValueArray {
-    values: Vec<Value>,
+    values: Vec<Slot>,
 }
 
 impl ValueArray {
@@ -17,18 +438,175 @@ impl ValueArray {
     }
 
     pub fn add(&mut self, value: Value) {
-        self.values.push(value)
+        self.values.push(Slot::from(value))
     }
 
-    pub fn len(&self) -> u8 {
-        self.values.len() as u8
+    pub fn len(&self) -> usize {
+        self.values.len()
     }
-}
 
-impl Index<usize> for ValueArray {
-    type Output = Value;
+    pub fn get(&self, idx: usize) -> Value {
+        Value::from(&self.values[idx])
+    }
 
-    fn index(&self, idx: usize) -> &Self::Output {
+    pub(crate) fn slot(&self, idx: usize) -> &Slot {
         &self.values[idx]
     }
+
+    /// Puts `value` in place of the constant at `idx` and returns that.
+    // Without `nan-boxing` a slot already is a value.
+    #[allow(clippy::useless_conversion)]
+    pub fn replace(&mut self, idx: usize, value: Value) -> Value {
+        Value::from(mem::replace(&mut self.values[idx], Slot::from(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falsey_values() {
+        assert!(Value::Nil.is_falsey());
+        assert!(Value::Bool(false).is_falsey());
+        assert!(!Value::Bool(true).is_falsey());
+        assert!(!Value::Number(0.0).is_falsey());
+        assert!(!Value::from("").is_falsey());
+    }
+
+    #[test]
+    fn print_values() {
+        let mut out = Vec::new();
+        for value in &[Value::Nil, true.into(), 2.5.into(), "lox".into()] {
+            print_value(&mut out, value).unwrap();
+            out.push(b' ');
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "nil true 2.5 lox ");
+    }
+
+    #[test]
+    fn format_numbers() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (1.0, "1"),
+            (-42.0, "-42"),
+            (2.5, "2.5"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (0.001, "0.001"),
+            (0.0005, "5.0E-4"),
+            (1234567.0, "1234567"),
+            (1e7, "1.0E7"),
+            (-12345678.9, "-1.23456789E7"),
+            (1e21, "1.0E21"),
+            (f64::MAX, "1.7976931348623157E308"),
+            (1.5e-10, "1.5E-10"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+            (f64::NAN, "nan"),
+        ];
+        for (number, text) in cases.iter() {
+            assert_eq!(format_number(*number), *text, "formatting {:?}", number);
+        }
+    }
+
+    #[test]
+    fn print_self_referencing_values() {
+        let list = Value::from(vec![Value::Number(1.0)]);
+        if let Value::List(elements) = &list {
+            elements.borrow_mut().push(list.clone());
+            elements.borrow_mut().push(Value::from(vec![list.clone()]));
+        }
+        assert_eq!(list.to_string(), "[1, [...], [[...]]]");
+
+        let map = Value::from(Table::new());
+        if let Value::Map(entries) = &map {
+            entries.borrow_mut().set(Key::Nil, map.clone());
+        }
+        assert_eq!(map.to_string(), "{nil: {...}}");
+
+        // Sharing without a cycle prints in full.
+        let shared = Value::from(vec![Value::Nil]);
+        let pair = Value::from(vec![shared.clone(), shared]);
+        assert_eq!(pair.to_string(), "[[nil], [nil]]");
+    }
+
+    #[test]
+    fn deeply_nested_lists() {
+        let mut list = Value::from(vec![]);
+        for _ in 0..300_000 {
+            list = Value::from(vec![Value::Nil, list]);
+        }
+        let depth = MAX_PRINT_DEPTH;
+        let printed = format!("{}[...]{}", "[nil, ".repeat(depth), "]".repeat(depth));
+        assert_eq!(list.to_string(), printed);
+    }
+
+    #[test]
+    fn deeply_nested_maps() {
+        let mut map = Value::from(Table::new());
+        for _ in 0..300_000 {
+            let mut outer = Table::new();
+            outer.set(Key::Nil, map);
+            map = Value::from(outer);
+        }
+        let depth = MAX_PRINT_DEPTH;
+        let printed = format!("{}{{...}}{}", "{nil: ".repeat(depth), "}".repeat(depth));
+        assert_eq!(map.to_string(), printed);
+    }
+
+    #[test]
+    fn map_keys() {
+        assert_eq!(
+            Key::from_value(&(-0.0).into()),
+            Key::from_value(&0.0.into())
+        );
+        assert_eq!(Key::from_value(&Value::Number(f64::NAN)), None);
+        assert_eq!(Key::from_value(&Value::from(vec![])), None);
+        assert!(Key::Nil < Key::Bool(false));
+        assert!(Key::Number(10.0) < Key::String(LoxString::from("1")));
+        assert!(Key::Number(2.0) < Key::Number(10.0));
+    }
+
+    #[test]
+    fn print_maps() {
+        let mut map = Table::new();
+        map.set(Key::String(LoxString::from("b")), Value::Nil);
+        map.set(Key::Number(1.0), Value::from(vec![]));
+        map.set(Key::String(LoxString::from("a")), Value::from(vec![]));
+        assert_eq!(Value::from(map).to_string(), "{1: [], a: [], b: nil}");
+    }
+
+    #[test]
+    fn print_lists() {
+        let inner = Value::from(vec![Value::from("a")]);
+        let list = Value::from(vec![1.0.into(), inner, Value::Nil]);
+        assert_eq!(list.to_string(), "[1, [a], nil]");
+        assert_eq!(Value::from(vec![]).to_string(), "[]");
+    }
+
+    #[test]
+    fn value_arrays() {
+        let mut constants = ValueArray::new();
+        constants.add(Value::Number(1.5));
+        constants.add(Value::from("name"));
+        assert_eq!(constants.len(), 2);
+        assert_eq!(constants.get(1), Value::from("name"));
+        assert_eq!(constants.slot(0).as_number(), Some(1.5));
+
+        assert_eq!(constants.replace(0, Value::Nil), Value::Number(1.5));
+        assert_eq!(constants.get(0), Value::Nil);
+    }
+
+    #[test]
+    fn functions_equal_by_identity() {
+        let function = Rc::new(Function::new(Some("f")));
+        let same = Value::Function(function.clone());
+        let other = Value::Function(Rc::new(Function::new(Some("f"))));
+
+        assert_eq!(Value::Function(function), same);
+        assert_ne!(same, other);
+        assert_eq!(same.to_string(), "<fn f>");
+    }
 }
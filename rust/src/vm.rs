@@ -1,89 +1,1678 @@
-use crate::chunk::{Chunk, OpCode};
+use crate::chunk::OpCode;
+use crate::compiler;
 use crate::debug;
+use crate::natives::{self, Random};
+use crate::object::{Function, Iter, LoxString, Native, NativeFn};
+use crate::optimizer;
 use crate::stack::Stack;
-use crate::value::{print_value, Value};
+use crate::table::Table;
+use crate::trace::Trace;
+use crate::value::{print_value, sorted_entries, Key, Slot, Value};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write as _;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum InterpretResult {
-    InterpretOk,
-    InterpretCompileError,
-    InterpretRuntimeError,
+const FRAMES_MAX: usize = 64;
+const STACK_MAX: usize = FRAMES_MAX * (u8::MAX as usize + 1);
+
+/// Why running Lox code failed. The messages have already been reported on
+/// the stderr sink of the VM by the time the error is returned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxError {
+    /// The source did not compile, one message per reported error.
+    Compile(Vec<String>),
+    /// The script stopped with a runtime error.
+    Runtime(String),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Compile(errors) => write!(f, "{}", errors.join("\n")),
+            LoxError::Runtime(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+/// Error returned by a native function. The VM reports it together with a
+/// stack trace of the Lox code that made the call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> RuntimeError {
+        RuntimeError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
-#[derive(Debug)]
-pub struct VM {
-    chunk: Chunk,
+impl Error for RuntimeError {}
+
+/// A function invocation in progress.
+struct CallFrame {
+    function: Rc<Function>,
     ip: usize,
-    stack: Stack<Value>,
+    /// Index of the first stack slot of the frame, which holds the callee.
+    slots: usize,
+    /// The `try` blocks of the function that are running, innermost last.
+    handlers: Vec<Handler>,
+    /// The exceptions its handlers caught, with the stack slot each one's
+    /// value went to, until `OP_RETHROW` throws them on.
+    caught: Vec<(usize, Exception)>,
 }
 
-impl VM {
-    pub fn new(chunk: Chunk) -> VM {
-        VM {
-            chunk,
-            ip: 0,
-            stack: Stack::new(256),
+/// Where execution continues when an exception is thrown inside a `try`.
+struct Handler {
+    ip: usize,
+    /// Height of the stack when the `try` started.
+    stack_len: usize,
+}
+
+/// An exception on its way to a handler.
+struct Exception {
+    value: Value,
+    message: String,
+    /// The stack trace at the point the exception was thrown.
+    trace: Vec<String>,
+}
+
+/// A Lox virtual machine. Global variables live as long as the VM, so
+/// consecutive calls to [`Vm::interpret`] see each other's definitions.
+pub struct Vm {
+    frames: Vec<CallFrame>,
+    stack: Stack<Slot>,
+    globals: Table<LoxString, Slot>,
+    /// The script compiled by the last call to [`Vm::interpret`].
+    script: Option<Rc<Function>>,
+    random: Random,
+    exception: Option<Exception>,
+    trace: Option<Trace>,
+    /// Whether the I/O natives may touch files and standard input.
+    io_enabled: bool,
+    /// Whether `/` and `%` by zero give IEEE infinities and NaN instead of
+    /// a runtime error.
+    ieee_division: bool,
+    /// Whether compiled code goes through the peephole optimizer.
+    optimize: bool,
+    /// How many instructions one call to [`Vm::interpret`] or [`Vm::call`]
+    /// may execute, and how many it has executed so far.
+    instruction_limit: Option<usize>,
+    instructions: usize,
+    /// How many bytes of strings, lists and maps one call may allocate, and
+    /// how many it has allocated so far.
+    allocation_limit: Option<usize>,
+    allocated: usize,
+    stdin: Box<dyn BufRead>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    /// Creates a VM with the built-in native functions defined.
+    pub fn new() -> Vm {
+        let mut vm = Vm {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: Stack::new(STACK_MAX),
+            globals: Table::new(),
+            script: None,
+            random: Random::new(seed_from_clock()),
+            exception: None,
+            trace: None,
+            io_enabled: false,
+            ieee_division: false,
+            optimize: true,
+            instruction_limit: None,
+            instructions: 0,
+            allocation_limit: None,
+            allocated: 0,
+            stdin: Box::new(BufReader::new(io::stdin())),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        };
+        natives::define_globals(&mut vm);
+        vm
+    }
+
+    /// Restarts the numbers returned by the `random()` native from `seed`.
+    /// Without a seed every VM gets a different sequence.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random.seed(seed);
+    }
+
+    /// Turns instruction tracing on, or off with `None`.
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Allows the file and input natives, `readFile`, `writeFile`,
+    /// `appendFile`, `fileExists` and `readLine`. I/O is disabled by default
+    /// so untrusted scripts cannot reach the file system; calling those
+    /// natives is then a runtime error.
+    pub fn set_io_enabled(&mut self, enabled: bool) {
+        self.io_enabled = enabled;
+    }
+
+    /// Lets `/` and `%` by zero follow IEEE 754, so `1 / 0` is `inf` and
+    /// `0 / 0` is `nan`. By default dividing by zero is a runtime error.
+    pub fn set_ieee_division(&mut self, enabled: bool) {
+        self.ieee_division = enabled;
+    }
+
+    /// Turns the peephole optimizer off, or back on. It is on by default;
+    /// turning it off gives the code exactly as the compiler emits it.
+    pub fn set_optimize(&mut self, enabled: bool) {
+        self.optimize = enabled;
+    }
+
+    /// Limits how many instructions each call to [`Vm::interpret`] or
+    /// [`Vm::call`] may execute. Going over is a runtime error, so scripts
+    /// that would loop forever stop instead. Calls that natives make back
+    /// into the VM count against the budget of the run that called the
+    /// native. `None` removes the limit.
+    pub fn set_instruction_limit(&mut self, limit: Option<usize>) {
+        self.instruction_limit = limit;
+    }
+
+    /// How many instructions the last call to [`Vm::interpret`] or
+    /// [`Vm::call`] executed.
+    pub fn instruction_count(&self) -> usize {
+        self.instructions
+    }
+
+    /// Limits how many bytes of strings, lists and maps each call to
+    /// [`Vm::interpret`] or [`Vm::call`] may allocate in total, memory
+    /// freed along the way included. Going over is a runtime error. The
+    /// count is an estimate that follows what a script builds, not what the
+    /// allocator hands out. `None` removes the limit.
+    pub fn set_allocation_limit(&mut self, limit: Option<usize>) {
+        self.allocation_limit = limit;
+    }
+
+    /// Sets where `readLine()` reads from.
+    pub fn set_stdin(&mut self, stdin: Box<dyn BufRead>) {
+        self.stdin = stdin;
+    }
+
+    /// Sets where the output of the script goes.
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.stdout = stdout;
+    }
+
+    /// Sets where compile and runtime errors are reported.
+    pub fn set_stderr(&mut self, stderr: Box<dyn Write>) {
+        self.stderr = stderr;
+    }
+
+    /// Compiles and runs `source`. Returns the value of the last statement
+    /// when it is an expression statement, `nil` otherwise.
+    pub fn interpret(&mut self, source: &str) -> Result<Value, LoxError> {
+        let function = self.compile(source)?;
+        self.reset_limits();
+
+        let base = self.frames.len();
+        let stack_len = self.stack.len();
+        self.push(Value::Function(function.clone()));
+        self.call_function(function, 0)
+            .and_then(|()| self.run(base))
+            .map_err(|error| self.uncaught(error, base, stack_len))
+    }
+
+    /// Compiles `source` without running it, so [`Vm::disassemble`] can
+    /// show the code. Compile errors are reported like
+    /// [`Vm::interpret`] does.
+    pub fn compile(&mut self, source: &str) -> Result<Rc<Function>, LoxError> {
+        match compiler::compile(source) {
+            Ok(mut function) => {
+                if self.optimize {
+                    optimizer::optimize(&mut function);
+                }
+                let function = Rc::new(function);
+                self.script = Some(function.clone());
+                Ok(function)
+            }
+            Err(errors) => {
+                for error in &errors {
+                    let _ = writeln!(self.stderr, "{}", error);
+                }
+                Err(LoxError::Compile(errors))
+            }
+        }
+    }
+
+    /// Calls the global function `name` with `args` and returns its result.
+    ///
+    /// Natives may call back into the VM with this. An error in such a
+    /// nested call is not reported; it unwinds to where the call started
+    /// and is returned to the native.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let base = self.frames.len();
+        let stack_len = self.stack.len();
+        let callee = match self.globals.get(name) {
+            Some(callee) => Value::from(callee),
+            None => {
+                let message = format!("Undefined variable '{}'.", name);
+                let error = self.runtime_error(&message);
+                return Err(self.uncaught(error, base, stack_len));
+            }
+        };
+
+        self.reset_limits();
+        self.push(callee.clone());
+        for arg in args {
+            self.push(arg.clone());
+        }
+        self.call_value(callee, args.len())
+            .and_then(|()| {
+                // Natives have already returned, Lox functions still have to run.
+                if self.frames.len() > base {
+                    self.run(base)
+                } else {
+                    Ok(self.pop())
+                }
+            })
+            .map_err(|error| self.uncaught(error, base, stack_len))
+    }
+
+    /// Exposes `function` to Lox as the global `name`. Calls with a number of
+    /// arguments other than `arity` are runtime errors.
+    ///
+    /// ```
+    /// use lox::{RuntimeError, Value, Vm};
+    ///
+    /// fn double(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    ///     match args[0] {
+    ///         Value::Number(n) => Ok(Value::Number(n * 2.0)),
+    ///         _ => Err(RuntimeError::new("Argument must be a number.")),
+    ///     }
+    /// }
+    ///
+    /// let mut vm = Vm::new();
+    /// vm.define_native("double", 1, double);
+    /// assert_eq!(vm.interpret("double(21);"), Ok(Value::Number(42.0)));
+    /// ```
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            function,
+        };
+        self.set_global(name, Value::Native(Rc::new(native)));
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).map(Value::from)
+    }
+
+    /// Defines the global `name`, or replaces its value when it exists.
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        let value: Value = value.into();
+        self.globals.set(LoxString::from(name), Slot::from(value));
+    }
+
+    /// Returns every global variable, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<(String, Value)> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::from(value)))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Disassembles the code compiled by the last call to [`Vm::interpret`],
+    /// including the functions it declares.
+    pub fn disassemble(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.script {
+            Some(script) => debug::disassemble_function(out, script),
+            None => Ok(()),
         }
     }
 
-    pub fn interpret(&mut self, _source: &str) -> InterpretResult {
-        return self.run();
+    /// Runs until the frame that sits at depth `base` of the frame stack
+    /// returns, and hands back its return value. Exceptions that no handler
+    /// above `base` catches end the run.
+    fn run(&mut self, base: usize) -> Result<Value, LoxError> {
+        loop {
+            match self.execute(base) {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    if !self.catch(base) {
+                        return Err(error);
+                    }
+                }
+            }
+        }
     }
 
-    fn run(&mut self) -> InterpretResult {
+    fn execute(&mut self, base: usize) -> Result<Value, LoxError> {
         loop {
-            if cfg!(debug_assertions) {
-                print!("          ");
-                for val in self.stack.iter() {
-                    print!("[{}]", val)
+            if let Some(limit) = self.instruction_limit {
+                if self.instructions >= limit {
+                    return Err(self.runtime_error("Instruction limit exceeded."));
                 }
-                println!();
-                debug::disassemble_instruction(&self.chunk, self.ip);
+            }
+            self.instructions += 1;
+            if let Some(trace) = &mut self.trace {
+                let frame = self.frames.last().unwrap();
+                let function = &frame.function;
+                trace.instruction(function.name(), &function.chunk, frame.ip, &self.stack);
             }
             match self.next() {
-                OpCode::OpReturn => {
-                    let val = self.stack.pop();
-                    print_value(val);
-                    return InterpretResult::InterpretOk;
-                }
-                OpCode::OpNegate => {
-                    let val = self.stack.pop();
-                    self.stack.push(-val)
-                }
-                OpCode::OpAdd => {
-                    let a = self.stack.pop();
-                    let b = self.stack.pop();
-                    self.stack.push(a + b)
-                }
-                OpCode::OpSubtract => {
-                    let a = self.stack.pop();
-                    let b = self.stack.pop();
-                    self.stack.push(a - b)
-                }
-                OpCode::OpMultiply => {
-                    let a = self.stack.pop();
-                    let b = self.stack.pop();
-                    self.stack.push(a * b)
-                }
-                OpCode::OpDivide => {
-                    let a = self.stack.pop();
-                    let b = self.stack.pop();
-                    self.stack.push(a / b)
-                }
-                OpCode::OpConstant => match self.next() {
-                    OpCode::Reg(idx) => {
-                        let constant = self.chunk.constants[idx as usize];
-                        self.stack.push(constant);
+                OpCode::OpConstant => {
+                    self.push_constant();
+                }
+                OpCode::OpNil => self.push(Value::Nil),
+                OpCode::OpTrue => self.push(Value::Bool(true)),
+                OpCode::OpFalse => self.push(Value::Bool(false)),
+                OpCode::OpPop => {
+                    self.stack.pop();
+                }
+                OpCode::OpGetLocal => {
+                    let slot = self.read_register() as usize;
+                    self.get_local(slot);
+                }
+                OpCode::OpSetLocal => {
+                    let slot = self.frame().slots + self.read_register() as usize;
+                    let value = self.stack.peek(0).clone();
+                    self.stack.set(slot, value);
+                }
+                OpCode::OpGetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value)
+                        }
+                        None => {
+                            let message = format!("Undefined variable '{}'.", name);
+                            return Err(self.runtime_error(&message));
+                        }
+                    }
+                }
+                OpCode::OpDefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.stack.pop();
+                    self.globals.set(name, value);
+                }
+                OpCode::OpSetGlobal => {
+                    let name = self.read_string();
+                    if !self.globals.contains(&name) {
+                        let message = format!("Undefined variable '{}'.", name);
+                        return Err(self.runtime_error(&message));
                     }
-                    _ => panic!("Register OpCode expected!"),
+                    let value = self.stack.peek(0).clone();
+                    self.globals.set(name, value);
+                }
+                OpCode::OpEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::Bool(a == b));
+                }
+                OpCode::OpGreater => self.binary_op(|a, b| Value::Bool(a > b))?,
+                OpCode::OpLess => self.binary_op(|a, b| Value::Bool(a < b))?,
+                OpCode::OpAdd => self.add()?,
+                OpCode::OpSubtract => self.binary_op(|a, b| Value::Number(a - b))?,
+                OpCode::OpMultiply => self.binary_op(|a, b| Value::Number(a * b))?,
+                OpCode::OpDivide => self.division_op(|a, b| Value::Number(a / b))?,
+                // Like C's fmod, the result takes the sign of the dividend.
+                OpCode::OpModulo => self.division_op(|a, b| Value::Number(a % b))?,
+                OpCode::OpNot => {
+                    let value = self.stack.pop();
+                    self.push(Value::Bool(value.is_falsey()));
+                }
+                OpCode::OpNegate => match self.stack.peek(0).as_number() {
+                    Some(value) => {
+                        self.stack.pop();
+                        self.push(Value::Number(-value));
+                    }
+                    _ => return Err(self.runtime_error("Operand must be a number.")),
                 },
+                OpCode::OpPrint => {
+                    let value = self.pop();
+                    let _ =
+                        print_value(&mut self.stdout, &value).and_then(|_| writeln!(self.stdout));
+                }
+                OpCode::OpJump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::OpJumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.stack.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::OpLoop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::OpIterInit => {
+                    let iterable = self.pop();
+                    let iter = self.iter(iterable)?;
+                    self.push(iter);
+                }
+                OpCode::OpIterNext => {
+                    let offset = self.read_short();
+                    let next = match Value::from(self.stack.peek(0)) {
+                        Value::Iter(iter) => iter.borrow_mut().next(),
+                        _ => panic!("Iterator expected!"),
+                    };
+                    match next {
+                        Some(value) => self.push(value),
+                        None => self.frame_mut().ip += offset,
+                    }
+                }
+                OpCode::OpTry => {
+                    let offset = self.read_short();
+                    let handler = Handler {
+                        ip: self.frame().ip + offset,
+                        stack_len: self.stack.len(),
+                    };
+                    self.frame_mut().handlers.push(handler);
+                }
+                OpCode::OpPopHandler => {
+                    self.frame_mut().handlers.pop();
+                }
+                OpCode::OpThrow => {
+                    let value = self.pop();
+                    return Err(self.throw(value));
+                }
+                OpCode::OpRethrow => {
+                    let slot = self.frame().slots + self.read_register() as usize;
+                    return Err(self.rethrow(slot));
+                }
+                OpCode::OpCall => {
+                    let arg_count = self.read_register() as usize;
+                    let callee = Value::from(self.stack.peek(arg_count));
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::OpBuildList => {
+                    let count = self.read_register() as usize;
+                    self.allocate_or_throw(count * mem::size_of::<Value>())?;
+                    let elements: Vec<Value> =
+                        self.stack.top(count).iter().map(Value::from).collect();
+                    let len = self.stack.len();
+                    self.stack.truncate(len - count);
+                    self.push(Value::from(elements));
+                }
+                OpCode::OpBuildMap => {
+                    let count = self.read_register() as usize;
+                    self.allocate_or_throw(count * mem::size_of::<(Key, Value)>())?;
+                    let mut map = Table::new();
+                    for pair in self.stack.top(count * 2).chunks(2) {
+                        let key = Value::from(&pair[0]);
+                        match Key::from_value(&key) {
+                            Some(key) => map.set(key, Value::from(&pair[1])),
+                            None => {
+                                let message = unhashable(&key);
+                                return Err(self.runtime_error(&message));
+                            }
+                        };
+                    }
+                    let len = self.stack.len();
+                    self.stack.truncate(len - count * 2);
+                    self.push(Value::from(map));
+                }
+                OpCode::OpBuildString => {
+                    let count = self.read_register() as usize;
+                    let mut text = String::new();
+                    for part in self.stack.top(count) {
+                        write!(text, "{}", part).expect("writing to a String cannot fail");
+                    }
+                    self.allocate_or_throw(text.len())?;
+                    let len = self.stack.len();
+                    self.stack.truncate(len - count);
+                    self.push(Value::from(text));
+                }
+                OpCode::OpIndexGet => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    let value = self.index_get(&target, &index)?;
+                    self.push(value);
+                }
+                OpCode::OpIndexSet => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let target = self.pop();
+                    self.index_set(&target, &index, value.clone())?;
+                    self.push(value);
+                }
+                OpCode::OpGetLocal0 => self.get_local(0),
+                OpCode::OpGetLocal1 => self.get_local(1),
+                OpCode::OpGetLocal2 => self.get_local(2),
+                OpCode::OpGetLocal3 => self.get_local(3),
+                OpCode::OpAddConstant => {
+                    self.push_constant();
+                    self.add()?;
+                }
+                OpCode::OpLessJumpIfFalse => {
+                    let offset = self.read_short();
+                    let less = match (
+                        self.stack.peek(1).as_number(),
+                        self.stack.peek(0).as_number(),
+                    ) {
+                        (Some(a), Some(b)) => a < b,
+                        _ => return Err(self.runtime_error("Operands must be numbers.")),
+                    };
+                    self.stack.pop();
+                    self.stack.pop();
+                    if !less {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::OpIncrementLocal => {
+                    let slot = self.frame().slots + self.read_register() as usize;
+                    let value = match self.stack.get(slot).as_number() {
+                        Some(value) => Slot::from(Value::Number(value + 1.0)),
+                        None => {
+                            let message = "Operands must be two numbers or two strings.";
+                            return Err(self.runtime_error(message));
+                        }
+                    };
+                    self.stack.set(slot, value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::OpReturn => {
+                    let result = self.stack.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.slots);
+                    if self.frames.len() == base {
+                        #[allow(clippy::useless_conversion)]
+                        return Ok(Value::from(result));
+                    }
+                    self.stack.push(result);
+                }
                 OpCode::Reg(_) => {}
             }
         }
     }
 
+    pub(crate) fn io_enabled(&self) -> bool {
+        self.io_enabled
+    }
+
+    pub(crate) fn stdin(&mut self) -> &mut dyn BufRead {
+        &mut *self.stdin
+    }
+
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
     fn next(&mut self) -> OpCode {
-        self.ip += 1;
-        self.chunk.code[self.ip - 1]
+        let frame = self.frame_mut();
+        frame.ip += 1;
+        frame.function.chunk.code[frame.ip - 1]
+    }
+
+    fn read_register(&mut self) -> u8 {
+        match self.next() {
+            OpCode::Reg(value) => value,
+            _ => panic!("Register OpCode expected!"),
+        }
+    }
+
+    fn read_short(&mut self) -> usize {
+        let high = self.read_register() as usize;
+        let low = self.read_register() as usize;
+        high << 8 | low
+    }
+
+    /// Pushes the constant the operand names, without converting it.
+    fn push_constant(&mut self) {
+        let idx = self.read_register() as usize;
+        let constant = self.frame().function.chunk.constants.slot(idx).clone();
+        self.stack.push(constant);
+    }
+
+    fn read_string(&mut self) -> LoxString {
+        let idx = self.read_register() as usize;
+        match self.frame().function.chunk.constants.get(idx) {
+            Value::String(name) => name,
+            _ => panic!("String constant expected!"),
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), LoxError> {
+        match callee {
+            Value::Function(function) => self.call_function(function, arg_count),
+            Value::Native(native) => self.call_native(&native, arg_count),
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call_function(&mut self, function: Rc<Function>, arg_count: usize) -> Result<(), LoxError> {
+        if arg_count != function.arity {
+            let message = format!(
+                "Expected {} arguments but got {}.",
+                function.arity, arg_count
+            );
+            return Err(self.runtime_error(&message));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            function,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+            handlers: Vec::new(),
+            caught: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn call_native(&mut self, native: &Native, arg_count: usize) -> Result<(), LoxError> {
+        if arg_count != native.arity {
+            let message = format!("Expected {} arguments but got {}.", native.arity, arg_count);
+            return Err(self.runtime_error(&message));
+        }
+
+        let args: Vec<Value> = self.stack.top(arg_count).iter().map(Value::from).collect();
+        let result = (native.function)(self, &args)
+            .and_then(|result| self.allocate(allocation_size(&result)).map(|_| result));
+        match result {
+            Ok(result) => {
+                // Drop the arguments and the native itself.
+                let len = self.stack.len();
+                self.stack.truncate(len - arg_count - 1);
+                self.push(result);
+                Ok(())
+            }
+            Err(error) => Err(self.runtime_error(&error.message)),
+        }
+    }
+
+    /// Returns an iterator over `iterable` for a `for in` loop.
+    ///
+    /// Only the built-in lists, maps, strings and iterators can be looped
+    /// over. Lox has no classes yet, so the protocol for instances, an
+    /// `iterator()` method returning an object whose `next()` gives the
+    /// elements, is not implemented. It belongs here and in `OP_ITER_NEXT`
+    /// once classes exist.
+    fn iter(&mut self, iterable: Value) -> Result<Value, LoxError> {
+        let iter = match iterable {
+            Value::Iter(_) => return Ok(iterable),
+            Value::List(list) => Iter::List { list, index: 0 },
+            Value::Map(map) => {
+                let keys = sorted_entries(&map.borrow())
+                    .into_iter()
+                    .map(|(key, _)| key.to_value())
+                    .collect();
+                Iter::Keys { keys, index: 0 }
+            }
+            Value::String(string) => Iter::Chars { string, offset: 0 },
+            _ => {
+                let message = format!("Cannot iterate over a {}.", iterable.type_name());
+                return Err(self.runtime_error(&message));
+            }
+        };
+        Ok(Value::from(iter))
+    }
+
+    fn index_get(&mut self, target: &Value, index: &Value) -> Result<Value, LoxError> {
+        match target {
+            Value::List(list) => {
+                let idx = self.list_index(index, list.borrow().len())?;
+                Ok(list.borrow()[idx].clone())
+            }
+            Value::Map(map) => {
+                let key = self.map_key(index)?;
+                let value = map.borrow().get(&key).cloned();
+                match value {
+                    Some(value) => Ok(value),
+                    None => {
+                        let message = format!("Key {} not found in map.", key);
+                        Err(self.runtime_error(&message))
+                    }
+                }
+            }
+            _ => Err(self.runtime_error("Only lists and maps can be indexed.")),
+        }
+    }
+
+    fn index_set(&mut self, target: &Value, index: &Value, value: Value) -> Result<(), LoxError> {
+        match target {
+            Value::List(list) => {
+                let idx = self.list_index(index, list.borrow().len())?;
+                list.borrow_mut()[idx] = value;
+                Ok(())
+            }
+            Value::Map(map) => {
+                let key = self.map_key(index)?;
+                map.borrow_mut().set(key, value);
+                Ok(())
+            }
+            _ => Err(self.runtime_error("Only lists and maps can be indexed.")),
+        }
+    }
+
+    fn map_key(&mut self, index: &Value) -> Result<Key, LoxError> {
+        match Key::from_value(index) {
+            Some(key) => Ok(key),
+            None => Err(self.runtime_error(&unhashable(index))),
+        }
+    }
+
+    /// Checks that `index` is a valid position in a list of `len` elements.
+    fn list_index(&mut self, index: &Value, len: usize) -> Result<usize, LoxError> {
+        match *index {
+            Value::Number(n) if n.fract() == 0.0 => {
+                if n >= 0.0 && n < len as f64 {
+                    Ok(n as usize)
+                } else {
+                    let message = format!("List index {} out of range for length {}.", index, len);
+                    Err(self.runtime_error(&message))
+                }
+            }
+            _ => Err(self.runtime_error("List index must be an integer.")),
+        }
+    }
+
+    fn get_local(&mut self, slot: usize) {
+        let value = self.stack.get(self.frame().slots + slot).clone();
+        self.stack.push(value);
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(Slot::from(value));
+    }
+
+    // Without `nan-boxing` a slot already is a value.
+    #[allow(clippy::useless_conversion)]
+    fn pop(&mut self) -> Value {
+        Value::from(self.stack.pop())
+    }
+
+    fn add(&mut self) -> Result<(), LoxError> {
+        let (a, b) = (self.stack.peek(1), self.stack.peek(0));
+        if a.as_number().is_some() && b.as_number().is_some() {
+            self.binary_op(|a, b| Value::Number(a + b))
+        } else if a.as_string().is_some() && b.as_string().is_some() {
+            self.concatenate()
+        } else {
+            Err(self.runtime_error("Operands must be two numbers or two strings."))
+        }
+    }
+
+    fn binary_op(&mut self, op: fn(f64, f64) -> Value) -> Result<(), LoxError> {
+        match (
+            self.stack.peek(1).as_number(),
+            self.stack.peek(0).as_number(),
+        ) {
+            (Some(a), Some(b)) => {
+                let result = op(a, b);
+                self.stack.pop();
+                self.stack.pop();
+                self.push(result);
+                Ok(())
+            }
+            _ => Err(self.runtime_error("Operands must be numbers.")),
+        }
+    }
+
+    fn division_op(&mut self, op: fn(f64, f64) -> Value) -> Result<(), LoxError> {
+        if let (Some(_), Some(divisor)) = (
+            self.stack.peek(1).as_number(),
+            self.stack.peek(0).as_number(),
+        ) {
+            if divisor == 0.0 && !self.ieee_division {
+                return Err(self.runtime_error("Division by zero."));
+            }
+        }
+        self.binary_op(op)
+    }
+
+    fn concatenate(&mut self) -> Result<(), LoxError> {
+        let len = match (
+            self.stack.peek(1).as_string(),
+            self.stack.peek(0).as_string(),
+        ) {
+            (Some(a), Some(b)) => a.len() + b.len(),
+            _ => 0,
+        };
+        self.allocate_or_throw(len)?;
+
+        let b = self.pop();
+        let a = self.pop();
+        let mut string = String::with_capacity(len);
+        let _ = write!(string, "{}{}", a, b);
+        self.push(Value::from(string));
+        Ok(())
+    }
+
+    /// Starts fresh instruction and allocation budgets, unless a native is
+    /// calling back into a run that is still going.
+    fn reset_limits(&mut self) {
+        if !self.frames.is_empty() {
+            return;
+        }
+        self.instructions = 0;
+        self.allocated = 0;
+    }
+
+    /// Counts `bytes` against the allocation limit.
+    fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        match self.allocation_limit {
+            Some(limit) if self.allocated > limit => {
+                Err(RuntimeError::new("Allocation limit exceeded."))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn allocate_or_throw(&mut self, bytes: usize) -> Result<(), LoxError> {
+        self.allocate(bytes)
+            .map_err(|error| self.runtime_error(&error.message))
+    }
+
+    /// Throws an error object, a map with the `message` and the `line` the
+    /// error happened on.
+    fn runtime_error(&mut self, message: &str) -> LoxError {
+        let line = match self.frames.last() {
+            Some(frame) => {
+                let line = frame
+                    .function
+                    .chunk
+                    .lines
+                    .get_line(frame.ip.saturating_sub(1));
+                Value::Number(line as f64)
+            }
+            None => Value::Nil,
+        };
+        let mut error = Table::new();
+        error.set(
+            Key::String(LoxString::from("message")),
+            Value::from(message),
+        );
+        error.set(Key::String(LoxString::from("line")), line);
+        self.throw(Value::from(error))
+    }
+
+    /// Starts unwinding with `value` as the exception. The returned error
+    /// makes `execute` stop, `run` then looks for a handler.
+    fn throw(&mut self, value: Value) -> LoxError {
+        let message = match &value {
+            Value::Map(map) => match map.borrow().get(&Key::String(LoxString::from("message"))) {
+                Some(Value::String(message)) => message.to_string(),
+                _ => value.to_string(),
+            },
+            _ => value.to_string(),
+        };
+
+        let mut trace = Vec::new();
+        for frame in self.frames.iter().rev() {
+            let function = &frame.function;
+            let line = function.chunk.lines.get_line(frame.ip.saturating_sub(1));
+            trace.push(match &function.name {
+                Some(name) => format!("[line {}] in {}()", line, name),
+                None => format!("[line {}] in script", line),
+            });
+        }
+
+        self.exception = Some(Exception {
+            value,
+            message: message.clone(),
+            trace,
+        });
+        LoxError::Runtime(message)
+    }
+
+    /// Throws the exception caught into stack slot `slot` again, keeping
+    /// the stack trace from where it was first thrown.
+    fn rethrow(&mut self, slot: usize) -> LoxError {
+        let caught = &mut self.frame_mut().caught;
+        let idx = caught
+            .iter()
+            .rposition(|(caught_slot, _)| *caught_slot == slot)
+            .expect("no exception to rethrow");
+        let (_, exception) = caught.remove(idx);
+        let message = exception.message.clone();
+        self.exception = Some(exception);
+        LoxError::Runtime(message)
+    }
+
+    /// Unwinds to the innermost handler in the frames above `base` and
+    /// pushes the exception for it. Returns `false` when there is none.
+    fn catch(&mut self, base: usize) -> bool {
+        while self.frames.len() > base {
+            let frame = self.frames.last_mut().unwrap();
+            if let Some(handler) = frame.handlers.pop() {
+                frame.ip = handler.ip;
+                // Exceptions caught into this slot or above are out of scope.
+                frame.caught.retain(|(slot, _)| *slot < handler.stack_len);
+                let exception = self.exception.take().expect("no exception to catch");
+                self.stack.truncate(handler.stack_len);
+                self.push(exception.value.clone());
+                self.frame_mut().caught.push((handler.stack_len, exception));
+                return true;
+            }
+            self.frames.pop();
+        }
+        false
+    }
+
+    /// Drops the frames and stack slots of a run that entered at frame
+    /// `base` with `stack_len` slots and ended with an exception nothing
+    /// caught. Only the outermost run reports it, with its stack trace; a
+    /// nested one leaves it to the native that started it.
+    fn uncaught(&mut self, error: LoxError, base: usize, stack_len: usize) -> LoxError {
+        if let Some(exception) = self.exception.take() {
+            if base == 0 {
+                let _ = writeln!(self.stderr, "{}", exception.message);
+                for line in &exception.trace {
+                    let _ = writeln!(self.stderr, "{}", line);
+                }
+            }
+        }
+
+        self.stack.truncate(stack_len);
+        self.frames.truncate(base);
+        error
+    }
+}
+
+/// The bytes a native's result adds to the allocation count. The elements
+/// of a new list or map are counted, not what they refer to.
+fn allocation_size(value: &Value) -> usize {
+    match value {
+        Value::String(string) => string.len(),
+        Value::List(list) => list.borrow().len() * mem::size_of::<Value>(),
+        Value::Map(map) => map.borrow().len() * mem::size_of::<(Key, Value)>(),
+        _ => 0,
+    }
+}
+
+pub(crate) fn unhashable(value: &Value) -> String {
+    match value {
+        Value::Number(_) => "NaN cannot be a map key.".to_string(),
+        _ => format!("A {} cannot be a map key.", value.type_name()),
+    }
+}
+
+fn seed_from_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+
+    fn run(source: &str) -> (Result<Value, LoxError>, String, String) {
+        let stdout = Capture::new();
+        let stderr = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_stdout(Box::new(stdout.clone()));
+        vm.set_stderr(Box::new(stderr.clone()));
+        let result = vm.interpret(source);
+        (result, stdout.contents(), stderr.contents())
+    }
+
+    fn compile_errors(source: &str) -> Vec<String> {
+        match run(source).0 {
+            Err(LoxError::Compile(errors)) => errors,
+            other => panic!("expected compile errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn capture_output() {
+        assert_eq!(
+            run("print (1 + 2) * 4 / 8 - 1;"),
+            (Ok(Value::Nil), "0.5\n".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn capture_compile_errors() {
+        let message = "[line 2] Error at end: Expect expression.";
+        assert_eq!(
+            run("1 +\n"),
+            (
+                Err(LoxError::Compile(vec![message.to_string()])),
+                String::new(),
+                format!("{}\n", message)
+            )
+        );
+    }
+
+    #[test]
+    fn capture_runtime_errors() {
+        let message = "Operands must be two numbers or two strings.";
+        assert_eq!(
+            run("print 1;\n1 + \"a\";"),
+            (
+                Err(LoxError::Runtime(message.to_string())),
+                "1\n".to_string(),
+                format!("{}\n[line 2] in script\n", message)
+            )
+        );
+    }
+
+    #[test]
+    fn last_expression_is_the_result() {
+        assert_eq!(run("1 + 2;").0, Ok(Value::Number(3.0)));
+        assert_eq!(run("var a = 1;").0, Ok(Value::Nil));
+        assert_eq!(run("if (true) 1;").0, Ok(Value::Nil));
+    }
+
+    #[test]
+    fn values_and_operators() {
+        assert_eq!(run("!nil == true;").0, Ok(Value::Bool(true)));
+        assert_eq!(run("1 < 2 and 2 <= 2;").0, Ok(Value::Bool(true)));
+        assert_eq!(run("3 >= 4 or 3 > 4;").0, Ok(Value::Bool(false)));
+        assert_eq!(run("nil or \"default\";").0, Ok(Value::from("default")));
+        assert_eq!(run("\"a\" + \"b\" == \"ab\";").0, Ok(Value::Bool(true)));
+        assert_eq!(run("1 != nil;").0, Ok(Value::Bool(true)));
+        assert_eq!(run("7 % 3 + 2 * 5 % 4;").0, Ok(Value::Number(3.0)));
+        assert_eq!(run("-7 % 3;").0, Ok(Value::Number(-1.0)));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let error = |line: usize| format!("Division by zero.\n[line {}] in script\n", line);
+        let (result, _, stderr) = run("var a = 1;\nprint a / 0;");
+        assert_eq!(
+            result,
+            Err(LoxError::Runtime("Division by zero.".to_string()))
+        );
+        assert_eq!(stderr, error(2));
+        assert_eq!(run("5 % 0;").2, error(1));
+        assert_eq!(run("0 / -0;").2, error(1));
+        assert_eq!(
+            run("\"a\" / 0;").2,
+            "Operands must be numbers.\n[line 1] in script\n"
+        );
+
+        let stdout = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_stdout(Box::new(stdout.clone()));
+        vm.set_ieee_division(true);
+        vm.interpret("print 1 / 0; print -1 / 0; print 0 / 0; print 5 % 0; print [1 / 0];")
+            .unwrap();
+        assert_eq!(stdout.contents(), "inf\n-inf\nnan\nnan\n[inf]\n");
+    }
+
+    #[test]
+    fn superinstructions() {
+        let source = "
+            fun sum(n) {
+              var total = 0;
+              for (var i = 0; i < n; i = i + 1) total = total + i;
+              return total + 0.5;
+            }
+            print sum(100);
+            var s = \"a\";
+            { var t = s; t = t + \"b\"; print t; }
+        ";
+        let count = |optimize: bool| {
+            let stdout = Capture::new();
+            let mut vm = Vm::new();
+            vm.set_optimize(optimize);
+            vm.set_stdout(Box::new(stdout.clone()));
+            vm.interpret(source).unwrap();
+            assert_eq!(stdout.contents(), "4950.5\nab\n");
+            vm.instruction_count()
+        };
+        assert!(count(true) * 5 < count(false) * 4);
+
+        let error =
+            |message: &str, line: usize| format!("{}\n[line {}] in script\n", message, line);
+        assert_eq!(
+            run("{ var a = \"a\";\na = a + 1; }").2,
+            error("Operands must be two numbers or two strings.", 2)
+        );
+        assert_eq!(
+            run("{ var a = \"a\";\nwhile (a < 1) {} }").2,
+            error("Operands must be numbers.", 2)
+        );
+    }
+
+    #[test]
+    fn instruction_and_allocation_limits() {
+        let stderr = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(stderr.clone()));
+        vm.set_instruction_limit(Some(1000));
+        vm.set_allocation_limit(Some(1 << 16));
+
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+        assert_eq!(
+            vm.interpret("while (true) {}"),
+            error("Instruction limit exceeded.")
+        );
+        assert_eq!(
+            vm.interpret("try { while (true) {} } catch (e) { print e; }"),
+            error("Instruction limit exceeded.")
+        );
+        assert_eq!(
+            vm.interpret("var s = \"ab\"; for (var i in range(0, 20, 1)) s = s + s;"),
+            error("Allocation limit exceeded.")
+        );
+        assert_eq!(
+            vm.interpret("var s = \"ab\"; for (var i in range(0, 20, 1)) s = join([s, s], \"\");"),
+            error("Allocation limit exceeded.")
+        );
+        assert!(stderr.contents().contains("[line 1] in script"));
+
+        // Each run starts with a fresh budget.
+        assert_eq!(
+            vm.interpret("var n = 0; while (n < 50) n = n + 1; n;"),
+            Ok(Value::Number(50.0))
+        );
+        assert_eq!(
+            vm.call("len", &[Value::from("abc")]),
+            Ok(Value::Number(3.0))
+        );
+    }
+
+    #[test]
+    fn variables_and_scopes() {
+        let source = "
+            var a = \"global\";
+            {
+              var a = \"outer\";
+              {
+                var a = \"inner\";
+                print a;
+              }
+              print a;
+            }
+            print a;
+            a = \"assigned\";
+            print a;
+        ";
+        assert_eq!(run(source).1, "inner\nouter\nglobal\nassigned\n");
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            var sum = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+              if (i == 2) sum = sum + 100; else sum = sum + i;
+            }
+            var n = 0;
+            while (n < 3) n = n + 1;
+            print sum;
+            print n;
+        ";
+        assert_eq!(run(source).1, "108\n3\n");
+    }
+
+    #[test]
+    fn list_literals_and_indexing() {
+        let source = "
+            var grid = [[1, 2], [3, 4],];
+            grid[1][0] = grid[0][1] * 10;
+            var alias = grid[0];
+            alias[0] = \"changed\";
+            print grid;
+            print [] == [];
+        ";
+        assert_eq!(run(source).1, "[[changed, 2], [20, 4]]\nfalse\n");
+
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+        assert_eq!(
+            run("[1, 2][2];").0,
+            error("List index 2 out of range for length 2.")
+        );
+        assert_eq!(run("[1][0.5];").0, error("List index must be an integer."));
+        assert_eq!(
+            run("var a = 1; a[0] = 2;").0,
+            error("Only lists and maps can be indexed.")
+        );
+    }
+
+    #[test]
+    fn deeply_nested_lists() {
+        let source = "
+            var l = [];
+            for (var i = 0; i < 300000; i = i + 1) l = [l];
+            print l;
+            { var local = [l]; }
+            l = nil;
+        ";
+        let printed = format!("{}[...]{}\n", "[".repeat(256), "]".repeat(256));
+        assert_eq!(run(source), (Ok(Value::Nil), printed, String::new()));
+    }
+
+    #[test]
+    fn map_literals_and_indexing() {
+        let source = "
+            var m = {\"b\": 1, \"a\": [2], 3: true,};
+            m[\"b\"] = m[\"b\"] + 10;
+            m[nil] = {};
+            { print m; }
+            print {} == {};
+        ";
+        assert_eq!(run(source).1, "{nil: {}, 3: true, a: [2], b: 11}\nfalse\n");
+
+        let error = |message: &str| Err(LoxError::Runtime(message.to_string()));
+        assert_eq!(
+            run("print {\"a\": 1}[\"b\"];").0,
+            error("Key b not found in map.")
+        );
+        assert_eq!(
+            run("print {[1]: 1};").0,
+            error("A list cannot be a map key.")
+        );
+        assert_eq!(
+            run("var m = {}; m[{}] = 1;").0,
+            error("A map cannot be a map key.")
+        );
+        assert_eq!(
+            run("print {}[sqrt(-1)];").0,
+            error("NaN cannot be a map key.")
+        );
+    }
+
+    #[test]
+    fn deeply_nested_maps() {
+        let source = "
+            var m = {};
+            for (var i = 0; i < 300000; i = i + 1) m = {\"m\": m};
+            print m;
+            { var local = [m]; }
+            m = nil;
+        ";
+        let printed = format!("{}{{...}}{}\n", "{m: ".repeat(256), "}".repeat(256));
+        assert_eq!(run(source), (Ok(Value::Nil), printed, String::new()));
+    }
+
+    #[test]
+    fn for_in_loops() {
+        let source = "
+            var total = 0;
+            for (var x in [1, 2, 3]) total = total + x;
+            print total;
+            for (var key in {\"b\": 1, \"a\": 2}) print key;
+            var letters = \"\";
+            for (var c in \"abc\") { var upper = c + c; letters = letters + upper; }
+            print letters;
+            var list = [1];
+            for (var x in list) if (x < 3) push(list, x + 1);
+            print list;
+            for (var i in range(3, 0, -1)) print i;
+            for (var x in []) print \"never\";
+        ";
+        assert_eq!(run(source).1, "6\na\nb\naabbcc\n[1, 2, 3]\n3\n2\n1\n");
+        assert_eq!(
+            run("for (var x in 1) {}").0,
+            Err(LoxError::Runtime(
+                "Cannot iterate over a number.".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        let source = "
+            print true ? \"yes\" : \"no\";
+            print nil ? \"yes\" : \"no\";
+            print false ? 1 : true ? 2 : 3;
+            print 1 or false ? \"or\" : \"binds tighter\";
+            var a = 1, 2;
+            print a;
+            var b;
+            b = 3, 4;
+            print b;
+            print false ? 1 : 2, 5;
+            print [1, 2][1];
+            fun pick(x, y) { return x ? y : x; }
+            print pick(true, 6);
+            var c = 0;
+            false ? c = 1 : c = 2;
+            print c;
+        ";
+        assert_eq!(run(source).1, "yes\nno\n2\nor\n2\n3\n5\n2\n6\n2\n");
+    }
+
+    #[test]
+    fn interpolate_strings() {
+        let source = r#"
+            var name = "Lox";
+            var count = 3;
+            print "Hello ${name}, you have ${count} items";
+            print "${count * 2}${nil}${[1, "two"]}";
+            print "outer ${"inner ${name + "!"}"} done";
+            print "${ {"a": 1}["a"] } \${not} $5";
+            print len("${count}");
+        "#;
+        assert_eq!(
+            run(source).1,
+            "Hello Lox, you have 3 items\n6nil[1, two]\nouter inner Lox! done\n1 ${not} $5\n1\n"
+        );
+    }
+
+    #[test]
+    fn catch_exceptions() {
+        let source = "
+            fun fail(value) { throw value; }
+            try {
+              var unused = 1;
+              fail(\"boom\");
+              print \"not reached\";
+            } catch (e) {
+              print \"caught \" + e;
+            }
+            try {
+              print nope;
+            } catch (e) {
+              print e[\"message\"];
+              print e[\"line\"];
+            }
+            try {
+              try { throw 1; } finally { print \"inner finally\"; }
+            } catch (e) {
+              print e + 1;
+            } finally {
+              print \"outer finally\";
+            }
+            try { print \"ok\"; } finally { print \"done\"; }
+            try { throw nil; } catch (e) { print e; }
+        ";
+        let (result, stdout, stderr) = run(source);
+        assert_eq!(result, Ok(Value::Nil));
+        assert_eq!(stderr, "");
+        assert_eq!(
+            stdout,
+            "caught boom\nUndefined variable 'nope'.\n11\n\
+             inner finally\n2\nouter finally\nok\ndone\nnil\n"
+        );
+    }
+
+    #[test]
+    fn catch_keeps_the_stack_consistent() {
+        let source = "
+            fun risky(n) {
+              var local = n * 2;
+              if (n > 2) throw \"too big\";
+              return local;
+            }
+            var results = [];
+            for (var i in range(0, 5, 1)) {
+              var item = i;
+              try { push(results, risky(item)); } catch (e) { push(results, e); }
+            }
+            results;
+        ";
+        assert_eq!(
+            run(source).0.unwrap().to_string(),
+            "[0, 2, 4, too big, too big]"
+        );
+    }
+
+    #[test]
+    fn uncaught_exceptions() {
+        let source = "fun f() {\n  throw \"oops\";\n}\ntry { f(); } finally { print \"cleanup\"; }";
+        let (result, stdout, stderr) = run(source);
+
+        assert_eq!(result, Err(LoxError::Runtime("oops".to_string())));
+        assert_eq!(stdout, "cleanup\n");
+        assert_eq!(stderr, "oops\n[line 2] in f()\n[line 4] in script\n");
+
+        let (_, _, stderr) = run("fun f() {\n  throw [1];\n}\nf();");
+        assert_eq!(stderr, "[1]\n[line 2] in f()\n[line 4] in script\n");
+
+        // Rethrowing after a finally clause keeps the trace of the throw,
+        // even when the finally clause caught another exception.
+        let source = "fun f() {\n  try {\n    print 1/0;\n  } finally {\n    \
+                      try { throw 2; } catch (e) {}\n  }\n}\ntry { f(); } catch (e) {}\nf();";
+        let (_, _, stderr) = run(source);
+        assert_eq!(
+            stderr,
+            "Division by zero.\n[line 3] in f()\n[line 9] in script\n"
+        );
+
+        // Rethrowing an error object keeps its message.
+        let (result, _, _) = run("try { -nil; } catch (e) { throw e; }");
+        assert_eq!(
+            result,
+            Err(LoxError::Runtime("Operand must be a number.".to_string()))
+        );
+    }
+
+    #[test]
+    fn native_errors_are_catchable() {
+        let (_, stdout, stderr) = run("try { pop([]); } catch (e) { print e[\"message\"]; }");
+        assert_eq!(stdout, "Cannot pop from an empty list.\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn try_errors() {
+        let source = "fun f() { try { return 1; } finally { print 2; } }";
+        assert_eq!(
+            compile_errors(source),
+            vec!["[line 1] Error at 'return': Cannot return from inside a 'try' with a 'finally' clause."]
+        );
+        assert!(run("fun f() { try { return 1; } catch (e) {} } print f();")
+            .2
+            .is_empty());
+        assert_eq!(
+            compile_errors("try {}"),
+            vec!["[line 1] Error at end: Expect 'catch' or 'finally' after try block."]
+        );
+        assert_eq!(
+            compile_errors("throw;"),
+            vec!["[line 1] Error at ';': Expect expression."]
+        );
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let (result, _, stderr) = run("print x;");
+        assert_eq!(
+            result,
+            Err(LoxError::Runtime("Undefined variable 'x'.".to_string()))
+        );
+        assert_eq!(stderr, "Undefined variable 'x'.\n[line 1] in script\n");
+
+        assert!(run("x = 1;").0.is_err());
+    }
+
+    #[test]
+    fn globals_from_rust() {
+        let mut vm = Vm::new();
+        vm.set_stdout(Box::new(Capture::new()));
+        vm.set_global("name", "lox");
+        vm.interpret("var greeting = \"hello \" + name;").unwrap();
+
+        assert_eq!(vm.get_global("greeting"), Some(Value::from("hello lox")));
+        assert_eq!(vm.get_global("missing"), None);
+        let scripted: Vec<(String, Value)> = vm
+            .globals()
+            .into_iter()
+            .filter(|(_, value)| !matches!(value, Value::Native(_)))
+            .collect();
+        assert_eq!(
+            scripted,
+            vec![
+                ("greeting".to_string(), Value::from("hello lox")),
+                ("name".to_string(), Value::from("lox")),
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_and_recursion() {
+        let source = "
+            fun fib(n) {
+              if (n < 2) return n;
+              return fib(n - 2) + fib(n - 1);
+            }
+            fun greet(name) { print \"hi \" + name; }
+            print fib(10);
+            print greet(\"bob\");
+            print fib;
+        ";
+        assert_eq!(run(source).1, "55\nhi bob\nnil\n<fn fib>\n");
+    }
+
+    #[test]
+    fn runtime_error_stack_trace() {
+        let source = "fun a() { b(); }\nfun b() {\n  c(1);\n}\nfun c() {}\na();";
+        let (result, _, stderr) = run(source);
+
+        assert_eq!(
+            result,
+            Err(LoxError::Runtime(
+                "Expected 0 arguments but got 1.".to_string()
+            ))
+        );
+        assert_eq!(
+            stderr,
+            "Expected 0 arguments but got 1.\n\
+             [line 3] in b()\n\
+             [line 1] in a()\n\
+             [line 6] in script\n"
+        );
+        assert!(run("fun f() { f(); } f();")
+            .2
+            .starts_with("Stack overflow."));
+        assert!(run("\"not a function\"();").0.is_err());
+    }
+
+    fn add(_vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+        match (&args[0], &args[1]) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            _ => Err(RuntimeError::new("Arguments must be numbers.")),
+        }
+    }
+
+    #[test]
+    fn native_functions() {
+        let stderr = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(stderr.clone()));
+        vm.define_native("add", 2, add);
+
+        assert_eq!(vm.interpret("add(1, 2);"), Ok(Value::Number(3.0)));
+        assert_eq!(vm.interpret("add;"), Ok(vm.get_global("add").unwrap()));
+        assert_eq!(vm.get_global("add").unwrap().to_string(), "<native fn>");
+
+        let result = vm.interpret("fun f() {\n  return add(1, nil);\n}\nf();");
+        assert_eq!(
+            result,
+            Err(LoxError::Runtime("Arguments must be numbers.".to_string()))
+        );
+        assert_eq!(
+            stderr.contents(),
+            "Arguments must be numbers.\n[line 2] in f()\n[line 4] in script\n"
+        );
+        assert!(vm.interpret("add(1);").is_err());
+    }
+
+    #[test]
+    fn call_from_rust() {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+        vm.define_native("add", 2, add);
+        vm.interpret("fun square(n) { return n * n; }").unwrap();
+
+        assert_eq!(vm.call("square", &[3.0.into()]), Ok(Value::Number(9.0)));
+        assert_eq!(
+            vm.call("add", &[1.0.into(), 2.0.into()]),
+            Ok(Value::Number(3.0))
+        );
+        assert!(vm.call("square", &[]).is_err());
+        assert!(vm.call("missing", &[]).is_err());
+        // The VM is still usable after a failed call.
+        assert_eq!(vm.call("square", &[4.0.into()]), Ok(Value::Number(16.0)));
+    }
+
+    /// Calls the global function named by its argument.
+    fn apply(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+        vm.call(&args[0].to_string(), &[])
+            .map_err(|error| RuntimeError::new(error.to_string()))
+    }
+
+    #[test]
+    fn natives_calling_back() {
+        let stderr = Capture::new();
+        let stdout = Capture::new();
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(stderr.clone()));
+        vm.set_stdout(Box::new(stdout.clone()));
+        vm.define_native("apply", 1, apply);
+
+        let source = "
+            fun one() { return 1; }
+            fun fail() { return 1 / 0; }
+            print apply(\"one\") + 1;
+            try {
+              apply(\"fail\");
+            } catch (e) {
+              print \"caught \" + e[\"message\"];
+            }
+            print \"after\";
+            apply(\"fail\");
+        ";
+        assert_eq!(
+            vm.interpret(source),
+            Err(LoxError::Runtime("Division by zero.".to_string()))
+        );
+        assert_eq!(stdout.contents(), "2\ncaught Division by zero.\nafter\n");
+        assert_eq!(
+            stderr.contents(),
+            "Division by zero.\n[line 11] in script\n"
+        );
+    }
+
+    #[test]
+    fn callbacks_share_the_limits() {
+        let mut vm = Vm::new();
+        vm.set_stderr(Box::new(Capture::new()));
+        vm.define_native("apply", 1, apply);
+        vm.interpret("fun spin() { var i = 0; while (i < 100) i = i + 1; }")
+            .unwrap();
+        vm.set_instruction_limit(Some(5000));
+
+        let error = Err(LoxError::Runtime("Instruction limit exceeded.".to_string()));
+        assert_eq!(
+            vm.interpret("for (var i = 0; i < 100; i = i + 1) apply(\"spin\");"),
+            error
+        );
+        // Every call from the host still starts with a full budget.
+        assert_eq!(vm.call("spin", &[]), Ok(Value::Nil));
     }
 }
@@ -0,0 +1,68 @@
+//! Counts the heap allocations of a script that only moves existing
+//! strings, lists and maps around. Pushing a value on the VM stack must
+//! not allocate, with or without the `nan-boxing` feature.
+
+use lox::{Value, Vm};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, counting the allocations made through it.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const ITERATIONS: usize = 10_000;
+
+#[test]
+fn moving_values_does_not_allocate() {
+    let source = format!(
+        "
+        fun area(shape) {{ return shape[\"width\"] * shape[\"height\"]; }}
+        var shape = {{\"name\": \"box\", \"width\": 2, \"height\": 3, \"area\": area}};
+        var names = [\"name\", \"width\"];
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            var name = shape[\"name\"];
+            var key = names[i % 2];
+            var value = shape[key];
+            total = total + shape[\"area\"](shape);
+        }}
+        total;
+        ",
+        ITERATIONS
+    );
+    let mut vm = Vm::new();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let total = vm.interpret(&source).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    assert_eq!(total, Value::Number(6.0 * ITERATIONS as f64));
+    // Compiling the script allocates a few hundred times; the loop must
+    // not add to that.
+    assert!(
+        allocations < ITERATIONS / 10,
+        "{} allocations for {} iterations",
+        allocations,
+        ITERATIONS
+    );
+}
@@ -0,0 +1,203 @@
+//! Runs every `.lox` file under `tests/programs` with the `lox` binary and
+//! checks it against the annotations in its comments, following the
+//! conventions of the craftinginterpreters test suite:
+//!
+//! - `// expect: <text>` is the next line the program prints.
+//! - `// expect runtime error: <message>` is an uncaught error raised on
+//!   that line. The program must exit with 70.
+//! - `// Error at '<lexeme>': <message>` is a compile error on that line,
+//!   and `// [line N] Error ...` one on line N. The program must exit
+//!   with 65.
+//!
+//! A program that expects no error must exit with 0 and print nothing to
+//! stderr.
+//!
+//! Programs in the language both implementations share can also be
+//! compared with the Kotlin tree-walker. Record its output with
+//! `java -cp <jlox classes> lox.LoxKt tests/programs/<name>.lox >
+//! tests/kotlin/<name>.out`, and the Rust binary's stdout is then required
+//! to match the recording exactly. Setting `LOX_KOTLIN_OUTPUTS` to another
+//! directory reads the recordings from there instead.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EXIT_COMPILE_ERROR: i32 = 65;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+#[derive(Debug, Default)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Expectations {
+        let mut expectations = Expectations::default();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            let comment = match line.find("//") {
+                Some(start) => line[start + 2..].trim(),
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect:") {
+                expectations.output.push(output.trim().to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error:") {
+                expectations.runtime_error = Some((message.trim().to_string(), line_number));
+            } else if comment.starts_with("Error") {
+                let error = format!("[line {}] {}", line_number, comment);
+                expectations.compile_errors.push(error);
+            } else if comment.starts_with("[line ") {
+                expectations.compile_errors.push(comment.to_string());
+            }
+        }
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            EXIT_COMPILE_ERROR
+        } else if self.runtime_error.is_some() {
+            EXIT_RUNTIME_ERROR
+        } else {
+            0
+        }
+    }
+}
+
+/// Runs the program at `path` and describes every way it differs from its
+/// annotations.
+fn check(path: &Path) -> Vec<String> {
+    let source = fs::read_to_string(path).expect("test programs are readable");
+    let expected = Expectations::parse(&source);
+    let result = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg(path)
+        .output()
+        .expect("the lox binary runs");
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let mut failures = Vec::new();
+
+    let output: Vec<&str> = stdout.lines().collect();
+    if output != expected.output {
+        failures.push(format!(
+            "expected output {:?}\n  got {:?}",
+            expected.output, output
+        ));
+    }
+
+    let errors: Vec<&str> = stderr.lines().collect();
+    if let Some((message, line)) = &expected.runtime_error {
+        let location = format!("[line {}]", line);
+        let reported =
+            errors.len() >= 2 && errors[0] == message && errors[1].starts_with(&location);
+        if !reported {
+            failures.push(format!(
+                "expected runtime error {:?} at {}\n  got {:?}",
+                message, location, errors
+            ));
+        }
+    } else if errors != expected.compile_errors {
+        failures.push(format!(
+            "expected errors {:?}\n  got {:?}",
+            expected.compile_errors, errors
+        ));
+    }
+
+    match result.status.code() {
+        Some(code) if code == expected.exit_code() => {}
+        code => failures.push(format!(
+            "expected exit code {}, got {:?}",
+            expected.exit_code(),
+            code
+        )),
+    }
+
+    if let Some(recorded) = kotlin_output(path) {
+        if stdout != recorded {
+            failures.push(format!(
+                "output differs from the Kotlin interpreter's\n  kotlin {:?}\n  rust   {:?}",
+                recorded, stdout
+            ));
+        }
+    }
+
+    failures
+}
+
+/// The recorded stdout of the Kotlin interpreter for the program at
+/// `path`, if there is one.
+fn kotlin_output(path: &Path) -> Option<String> {
+    let dir = match env::var_os("LOX_KOTLIN_OUTPUTS") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/kotlin"),
+    };
+    let name = path
+        .strip_prefix(programs_dir())
+        .ok()?
+        .with_extension("out");
+    fs::read_to_string(dir.join(name)).ok()
+}
+
+fn programs_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs")
+}
+
+fn collect_programs(dir: &Path, programs: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("the programs directory is readable") {
+        let path = entry.expect("directory entries are readable").path();
+        if path.is_dir() {
+            collect_programs(&path, programs);
+        } else if path.extension() == Some("lox".as_ref()) {
+            programs.push(path);
+        }
+    }
+}
+
+#[test]
+fn programs() {
+    let mut programs = Vec::new();
+    collect_programs(&programs_dir(), &mut programs);
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs found");
+
+    let mut report = String::new();
+    for path in &programs {
+        let failures = check(path);
+        if !failures.is_empty() {
+            report.push_str(&format!("\n{}:\n", path.display()));
+            for failure in failures {
+                report.push_str(&format!("  {}\n", failure));
+            }
+        }
+    }
+    assert!(report.is_empty(), "failing programs:{}", report);
+}
+
+#[test]
+fn parse_annotations() {
+    let source = "print 1; // expect: 1\n\
+                  print x; // expect runtime error: Undefined variable 'x'.\n\
+                  var = 1; // Error at '=': Expect variable name.\n\
+                  // [line 5] Error at end: Expect ';' after value.\n";
+    let expectations = Expectations::parse(source);
+
+    assert_eq!(expectations.output, vec!["1"]);
+    assert_eq!(
+        expectations.runtime_error,
+        Some(("Undefined variable 'x'.".to_string(), 2))
+    );
+    assert_eq!(
+        expectations.compile_errors,
+        vec![
+            "[line 3] Error at '=': Expect variable name.",
+            "[line 5] Error at end: Expect ';' after value."
+        ]
+    );
+    assert_eq!(expectations.exit_code(), EXIT_COMPILE_ERROR);
+}